@@ -0,0 +1,235 @@
+use crate::error::{Error, ErrorKind, Result};
+use crate::kv_store::root_key;
+use crate::UnQLite;
+use std::error;
+use std::fmt;
+
+/// A set of writes applied atomically.
+///
+/// Operations are collected in memory and applied in order by [`apply`](#method.apply) inside a
+/// single transaction: either all of them reach the database or none does.
+///
+/// ```
+/// # extern crate unqlite;
+/// #
+/// use unqlite::{UnQLite, WriteBatch, KV};
+///
+/// # #[cfg(feature = "enable-threads")]
+/// # fn main() {
+/// let unqlite = UnQLite::create_temp();
+/// unqlite.kv_store("old", "value").unwrap();
+///
+/// let mut batch = WriteBatch::new();
+/// batch.put("key", "value").append("key", "!").delete("old");
+/// batch.apply(&unqlite).unwrap();
+///
+/// assert_eq!(unqlite.kv_fetch("key").unwrap(), b"value!");
+/// assert!(!unqlite.kv_contains("old"));
+/// # }
+/// # #[cfg(not(feature = "enable-threads"))]
+/// # fn main() { }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+/// A single operation of a `WriteBatch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchOp {
+    /// Store `value` under `key`, see `KV::kv_store`
+    Put { key: Vec<u8>, value: Vec<u8> },
+    /// Append `value` to `key`, see `KV::kv_append`
+    Append { key: Vec<u8>, value: Vec<u8> },
+    /// Remove `key`, see `KV::kv_delete`
+    Delete { key: Vec<u8> },
+}
+
+/// Error from [`WriteBatch::apply`](struct.WriteBatch.html#method.apply).
+#[derive(Debug)]
+pub struct BatchError {
+    /// Index of the failed operation in the batch, `None` if beginning or committing the
+    /// transaction failed
+    pub index: Option<usize>,
+    /// The failed operation
+    pub op: Option<BatchOp>,
+    /// The underlying error
+    pub error: Error,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    /// Store `value` under `key`.
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> &mut Self {
+        self.ops.push(BatchOp::Put {
+            key: key.as_ref().to_vec(),
+            value: value.as_ref().to_vec(),
+        });
+        self
+    }
+
+    /// Append `value` to the record under `key`.
+    pub fn append<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> &mut Self {
+        self.ops.push(BatchOp::Append {
+            key: key.as_ref().to_vec(),
+            value: value.as_ref().to_vec(),
+        });
+        self
+    }
+
+    /// Remove the record under `key`. Removing a missing record is not an error.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> &mut Self {
+        self.ops.push(BatchOp::Delete {
+            key: key.as_ref().to_vec(),
+        });
+        self
+    }
+
+    /// The collected operations, in order.
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    /// Number of collected operations.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether no operation was collected.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Remove every collected operation.
+    pub fn clear(&mut self) {
+        self.ops.clear()
+    }
+
+    /// Apply every operation within a single transaction.
+    ///
+    /// If an operation fails, the transaction is rolled back and the error tells which
+    /// operation it was. The write lock of the handle is held throughout, and the transaction
+    /// is handled as for [`KV::kv_compare_and_swap`](trait.KV.html#tymethod.kv_compare_and_swap):
+    /// changes made on `unqlite` since the last commit are committed beforehand, and while a
+    /// transaction begun with [`Transaction::begin`](trait.Transaction.html#tymethod.begin) is
+    /// open, the batch is part of it and rolling back on failure is left to the application.
+    ///
+    /// Fails with `ErrorKind::NOTIMPLEMENTED` if the engine is not transactional, see
+    /// [`UnQLite::is_transactional`](struct.UnQLite.html#method.is_transactional).
+    pub fn apply(&self, unqlite: &UnQLite) -> ::std::result::Result<(), BatchError> {
+        let mut failed = None;
+        unqlite
+            .check_transactional()
+            .and_then(|_| {
+                unqlite.locked_update(|| {
+                    for (index, op) in self.ops.iter().enumerate() {
+                        failed = Some(index);
+                        op.apply(unqlite)?;
+                    }
+                    failed = None;
+                    Ok(())
+                })
+            })
+            .map_err(|error| BatchError {
+                index: failed,
+                op: failed.map(|index| self.ops[index].clone()),
+                error,
+            })
+    }
+}
+
+impl BatchOp {
+    /// Apply the operation, under the update lock.
+    fn apply(&self, unqlite: &UnQLite) -> Result<()> {
+        match *self {
+            BatchOp::Put { ref key, ref value } => {
+                unqlite.kv_store_unlocked(root_key(key)?, value)
+            }
+            BatchOp::Append { ref key, ref value } => {
+                unqlite.kv_append_unlocked(root_key(key)?, value)
+            }
+            BatchOp::Delete { ref key } => match unqlite.kv_delete_unlocked(root_key(key)?) {
+                Err(ref err) if err.kind() == Some(ErrorKind::NOTFOUND) => Ok(()),
+                result => result,
+            },
+        }
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "write batch operation #{} failed: {}", index, self.error),
+            None => write!(f, "write batch transaction failed: {}", self.error),
+        }
+    }
+}
+
+impl error::Error for BatchError {}
+
+impl From<BatchError> for Error {
+    fn from(err: BatchError) -> Error {
+        err.error
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "enable-threads")]
+mod tests {
+    use super::{BatchOp, WriteBatch};
+    use crate::{Transaction, UnQLite, KV};
+
+    #[test]
+    fn apply() {
+        let unqlite = UnQLite::create_temp();
+        unqlite.kv_store("old", "value").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put("a", "1")
+            .put("b", "2")
+            .append("b", "2")
+            .delete("old")
+            .delete("missing");
+        assert_eq!(batch.len(), 5);
+        batch.apply(&unqlite).unwrap();
+
+        assert_eq!(unqlite.kv_fetch("a").unwrap(), b"1");
+        assert_eq!(unqlite.kv_fetch("b").unwrap(), b"22");
+        assert!(!unqlite.kv_contains("old"));
+
+        batch.clear();
+        assert!(batch.is_empty());
+        batch.apply(&unqlite).unwrap();
+    }
+
+    #[test]
+    fn rollback() {
+        let unqlite = UnQLite::create_temp();
+        unqlite.kv_store("key", "value").unwrap();
+        unqlite.commit().unwrap();
+        unqlite.kv_store("pending", "value").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch
+            .put("other", "value")
+            .delete("key")
+            .put("", "empty key");
+        let err = batch.apply(&unqlite).unwrap_err();
+        assert_eq!(err.index, Some(2));
+        assert_eq!(
+            err.op,
+            Some(BatchOp::Put {
+                key: Vec::new(),
+                value: b"empty key".to_vec(),
+            })
+        );
+        assert!(!unqlite.kv_contains("other"));
+        assert!(unqlite.kv_contains("key"));
+        assert!(unqlite.kv_contains("pending"));
+    }
+}
@@ -0,0 +1,432 @@
+use crate::error::{ErrorKind, Result};
+use crate::kv_cursor::prefix_range;
+use crate::stream::{KvReader, KvWriter};
+use crate::{CompareAndSwapError, Cursor, Direction, Entry, Iter, KvCursor, UnQLite, KV};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::{ControlFlow, RangeBounds};
+use std::os::raw::c_void;
+use std::time::Duration;
+
+/// A named keyspace inside a database.
+///
+/// A bucket prefixes its keys with `\0`, its name and `\0`, so buckets never share keys with
+/// each other. Keys of the database starting with a NUL byte are thus reserved: the `KV` methods
+/// of the database reject them with `ErrorKind::INVALID`, and its cursors skip them.
+///
+/// The `KV` and `Cursor` interfaces of a bucket only see its own records, with their keys as
+/// stored through the bucket. Engine settings such as
+/// [`kv_set_hash`](trait.KV.html#tymethod.kv_set_hash) apply to the whole database.
+///
+/// ```
+/// # extern crate unqlite;
+/// #
+/// use unqlite::{Cursor, UnQLite, KV};
+///
+/// # #[cfg(feature = "enable-threads")]
+/// # fn main() {
+/// let unqlite = UnQLite::create_in_memory();
+/// let users = unqlite.bucket("users").unwrap();
+/// let groups = unqlite.bucket("groups").unwrap();
+/// users.kv_store("1", "alice").unwrap();
+/// groups.kv_store("1", "admin").unwrap();
+///
+/// assert_eq!(users.kv_fetch("1").unwrap(), b"alice");
+/// assert_eq!(users.first().unwrap().key(), b"1");
+/// assert!(!unqlite.kv_contains("1"));
+/// assert_eq!(unqlite.buckets(), ["groups", "users"]);
+///
+/// unqlite.drop_bucket("users").unwrap();
+/// assert_eq!(unqlite.buckets(), ["groups"]);
+/// # }
+/// # #[cfg(not(feature = "enable-threads"))]
+/// # fn main() { }
+/// ```
+#[derive(Clone)]
+pub struct Bucket<'db> {
+    unqlite: &'db UnQLite,
+    name: String,
+    prefix: Vec<u8>,
+}
+
+impl UnQLite {
+    /// Open the bucket `name`.
+    ///
+    /// Buckets exist as long as they hold records, so this never writes to the database.
+    /// Returns `ErrorKind::INVALID` if `name` contains a NUL byte.
+    pub fn bucket(&self, name: &str) -> Result<Bucket<'_>> {
+        if name.contains('\0') {
+            return Err(ErrorKind::INVALID.into());
+        }
+        let mut prefix = Vec::with_capacity(name.len() + 2);
+        prefix.push(0);
+        prefix.extend_from_slice(name.as_bytes());
+        prefix.push(0);
+        Ok(Bucket {
+            unqlite: self,
+            name: name.to_owned(),
+            prefix,
+        })
+    }
+
+    /// Names of the buckets holding records, in order.
+    ///
+    /// This walks through every record of the database starting with a NUL byte.
+    pub fn buckets(&self) -> Vec<String> {
+        let mut names = BTreeSet::new();
+        let mut entry = Entry::first(self, vec![0]);
+        while let Some(current) = entry {
+            let key = current.key();
+            if let Some(end) = key.iter().position(|&b| b == 0) {
+                if let Ok(name) = String::from_utf8(key[..end].to_vec()) {
+                    names.insert(name);
+                }
+            }
+            entry = current.next();
+        }
+        names.into_iter().collect()
+    }
+
+    /// Remove every record of the bucket `name`.
+    pub fn drop_bucket(&self, name: &str) -> Result<()> {
+        self.bucket(name)?.clear()
+    }
+}
+
+impl<'db> Bucket<'db> {
+    /// Name of the bucket.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The underlying database.
+    pub fn unqlite(&self) -> &'db UnQLite {
+        self.unqlite
+    }
+
+    /// Remove every record of the bucket, within a single transaction as
+    /// [`KV::kv_delete_range`](trait.KV.html#tymethod.kv_delete_range).
+    pub fn clear(&self) -> Result<()> {
+        self.unqlite
+            .delete_range::<&[u8], _>(&self.prefix, ..)
+            .map(|_| ())
+    }
+
+    fn key<K: AsRef<[u8]>>(&self, key: K) -> Vec<u8> {
+        let mut prefixed = self.prefix.clone();
+        prefixed.extend_from_slice(key.as_ref());
+        prefixed
+    }
+}
+
+impl<'db> fmt::Debug for Bucket<'db> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bucket").field("name", &self.name).finish()
+    }
+}
+
+impl<'db> KV for Bucket<'db> {
+    fn kv_store<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.unqlite.raw().kv_store(self.key(key), value)
+    }
+
+    fn kv_append<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.unqlite.raw().kv_append(self.key(key), value)
+    }
+
+    fn kv_delete<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.unqlite.raw().kv_delete(self.key(key))
+    }
+
+    fn kv_delete_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<usize> {
+        self.unqlite.delete_range(&self.prefix, range)
+    }
+
+    fn kv_delete_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<usize> {
+        self.unqlite
+            .delete_range(&self.prefix, prefix_range(prefix.as_ref()))
+    }
+
+    fn kv_contains<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.unqlite.raw().kv_contains(self.key(key))
+    }
+
+    fn kv_fetch_length<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
+        self.unqlite.raw().kv_fetch_length(self.key(key))
+    }
+
+    fn kv_fetch<K: AsRef<[u8]>>(&self, key: K) -> Result<Vec<u8>> {
+        self.unqlite.raw().kv_fetch(self.key(key))
+    }
+
+    fn kv_fetch_into<K: AsRef<[u8]>>(&self, key: K, buf: &mut Vec<u8>) -> Result<()> {
+        self.unqlite.raw().kv_fetch_into(self.key(key), buf)
+    }
+
+    fn kv_fetch_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<Vec<u8>>>> {
+        let keys: Vec<_> = keys.iter().map(|key| self.key(key)).collect();
+        self.unqlite.raw().kv_fetch_many(&keys)
+    }
+
+    fn kv_contains_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<bool>> {
+        let keys: Vec<_> = keys.iter().map(|key| self.key(key)).collect();
+        self.unqlite.raw().kv_contains_many(&keys)
+    }
+
+    fn kv_fetch_callback<K, F>(&self, key: K, consumer: F) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8]) -> ControlFlow<()>,
+    {
+        self.unqlite.raw().kv_fetch_callback(self.key(key), consumer)
+    }
+
+    fn kv_reader<K: AsRef<[u8]>>(&self, key: K) -> Result<KvReader<'_>> {
+        KvReader::new(self.unqlite, &self.key(key))
+    }
+
+    fn kv_writer<K: AsRef<[u8]>>(&self, key: K) -> KvWriter<'_> {
+        KvWriter::new(self.unqlite, &self.key(key))
+    }
+
+    fn kv_store_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.unqlite.raw().kv_store_with_ttl(self.key(key), value, ttl)
+    }
+
+    fn kv_ttl<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Duration>> {
+        self.unqlite.raw().kv_ttl(self.key(key))
+    }
+
+    fn kv_persist<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.unqlite.raw().kv_persist(self.key(key))
+    }
+
+    fn kv_insert_new<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.unqlite.raw().kv_insert_new(self.key(key), value)
+    }
+
+    fn kv_replace<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.unqlite.raw().kv_replace(self.key(key), value)
+    }
+
+    fn kv_incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        self.unqlite.raw().kv_incr(self.key(key), delta)
+    }
+
+    fn kv_decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        self.unqlite.raw().kv_decr(self.key(key), delta)
+    }
+
+    fn kv_compare_and_swap<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<::std::result::Result<(), CompareAndSwapError>> {
+        self.unqlite
+            .raw()
+            .kv_compare_and_swap(self.key(key), expected, new)
+    }
+
+    fn kv_update<K, F>(&self, key: K, f: F) -> Result<Option<Vec<u8>>>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        self.unqlite.raw().kv_update(self.key(key), f)
+    }
+
+    fn kv_merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        name: &str,
+        operand: V,
+    ) -> Result<()> {
+        self.unqlite.raw().kv_merge(self.key(key), name, operand)
+    }
+
+    fn kv_config_hash(
+        &self,
+        hash: extern "C" fn(key: *const c_void, len: u32) -> u32,
+    ) -> Result<()> {
+        self.unqlite.kv_config_hash(hash)
+    }
+
+    fn kv_config_cmp(
+        &self,
+        cmp: extern "C" fn(lhs: *const c_void, rhs: *const c_void, len: u32) -> i32,
+    ) -> Result<()> {
+        self.unqlite.kv_config_cmp(cmp)
+    }
+
+    fn kv_set_hash<F>(&self, hash: F) -> Result<()>
+    where
+        F: Fn(&[u8]) -> u32 + Send + Sync + 'static,
+    {
+        self.unqlite.kv_set_hash(hash)
+    }
+
+    fn kv_set_cmp<F>(&self, cmp: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static,
+    {
+        self.unqlite.kv_set_cmp(cmp)
+    }
+
+    fn kv_set_key_eq<F>(&self, eq: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.unqlite.kv_set_key_eq(eq)
+    }
+}
+
+impl<'db> Cursor for Bucket<'db> {
+    fn first(&self) -> Option<Entry<'_>> {
+        Entry::first(self.unqlite, self.prefix.clone())
+    }
+
+    fn last(&self) -> Option<Entry<'_>> {
+        Entry::last(self.unqlite, self.prefix.clone())
+    }
+
+    fn seek<K: AsRef<[u8]>>(&self, key: K, pos: Direction) -> Option<Entry<'_>> {
+        Entry::seek(self.unqlite, self.prefix.clone(), key.as_ref(), pos)
+    }
+
+    fn cursor(&self) -> Result<KvCursor<'_>> {
+        KvCursor::open(self.unqlite, self.prefix.clone())
+    }
+
+    fn iter(&self) -> Iter<'_> {
+        Iter::new(self.unqlite, self.prefix.clone())
+    }
+
+    fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Iter<'_> {
+        Iter::range(self.unqlite, self.prefix.clone(), range)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "enable-threads")]
+mod tests {
+    use crate::{Cursor, Direction, ErrorKind, UnQLite, KV};
+    use std::io::Read;
+    use std::ops::ControlFlow;
+
+    #[test]
+    fn isolation() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("key", "root").unwrap();
+        let a = unqlite.bucket("a").unwrap();
+        let ab = unqlite.bucket("ab").unwrap();
+        a.kv_store("key", "a").unwrap();
+        a.kv_append("key", "!").unwrap();
+        ab.kv_store("key", "ab").unwrap();
+        ab.kv_store("other", "ab").unwrap();
+
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"root");
+        assert_eq!(a.kv_fetch("key").unwrap(), b"a!");
+        assert_eq!(ab.kv_fetch_length("key").unwrap(), 2);
+        assert!(!a.kv_contains("other"));
+
+        let mut value = String::new();
+        a.kv_reader("key")
+            .unwrap()
+            .read_to_string(&mut value)
+            .unwrap();
+        assert_eq!(value, "a!");
+
+        assert_eq!(unqlite.buckets(), ["a", "ab"]);
+        assert_eq!(
+            unqlite.bucket("nul\0").unwrap_err().kind(),
+            Some(ErrorKind::INVALID)
+        );
+    }
+
+    #[test]
+    fn cursor() {
+        let unqlite = UnQLite::create_in_memory();
+        let bucket = unqlite.bucket("bucket").unwrap();
+        unqlite.kv_store("root", "0").unwrap();
+        bucket.kv_store("a", "1").unwrap();
+        unqlite.bucket("other").unwrap().kv_store("b", "2").unwrap();
+        bucket.kv_store("c", "3").unwrap();
+
+        let mut keys = Vec::new();
+        let mut entry = bucket.first();
+        while let Some(current) = entry {
+            keys.push(current.key());
+            entry = current.next();
+        }
+        keys.sort();
+        assert_eq!(keys, [b"a", b"c"]);
+
+        let entry = bucket.seek("c", Direction::Exact).unwrap();
+        assert_eq!(entry.key_value(), (b"c".to_vec(), b"3".to_vec()));
+        assert_eq!(entry.key_len(), 1);
+        let mut key = Vec::new();
+        entry
+            .key_callback(|data| {
+                key.extend_from_slice(data);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(key, b"c");
+        assert!(bucket.seek("b", Direction::Exact).is_none());
+        assert!(bucket.last().is_some());
+
+        let mut records: Vec<_> = bucket.iter().rev().map(|record| record.unwrap()).collect();
+        records.sort();
+        assert_eq!(records, [(b"a".to_vec(), b"1".to_vec()), (b"c".to_vec(), b"3".to_vec())]);
+    }
+
+    #[test]
+    fn drop_bucket() {
+        let unqlite = UnQLite::create_in_memory();
+        let bucket = unqlite.bucket("bucket").unwrap();
+        for i in 0..100 {
+            bucket.kv_store(i.to_string(), "value").unwrap();
+        }
+        unqlite.kv_store("root", "value").unwrap();
+        assert_eq!(unqlite.kv_delete_prefix("").unwrap(), 1);
+        assert_eq!(bucket.kv_delete_range("50"..).unwrap(), 54);
+        unqlite.kv_store("root", "value").unwrap();
+
+        unqlite.drop_bucket("bucket").unwrap();
+        assert!(bucket.first().is_none());
+        assert!(unqlite.buckets().is_empty());
+        assert!(unqlite.kv_contains("root"));
+    }
+
+    #[test]
+    fn reserved_keys() {
+        let unqlite = UnQLite::create_in_memory();
+        let bucket = unqlite.bucket("bucket").unwrap();
+        bucket.kv_store("key", "value").unwrap();
+
+        let err = unqlite.kv_store("\0bucket\0key", "other").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::INVALID));
+        let err = unqlite.kv_store("\0phantom\0", "value").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::INVALID));
+        assert!(!unqlite.kv_contains("\0bucket\0key"));
+        let fetched = unqlite.kv_fetch_many(&["\0bucket\0key"]);
+        assert_eq!(
+            fetched[0].as_ref().unwrap_err().kind(),
+            Some(ErrorKind::INVALID)
+        );
+
+        assert!(unqlite.first().is_none());
+        assert_eq!(unqlite.iter().count(), 0);
+        assert!(unqlite.seek("\0bucket\0key", Direction::Exact).is_none());
+        assert_eq!(unqlite.kv_delete_prefix("").unwrap(), 0);
+        assert_eq!(unqlite.buckets(), ["bucket"]);
+        assert_eq!(bucket.kv_fetch("key").unwrap(), b"value");
+    }
+}
@@ -0,0 +1,243 @@
+use crate::error::ErrorKind;
+use crate::vars::{UNQLITE_BUSY, UNQLITE_LOCKED};
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What to do when the engine reports `UNQLITE_LOCKED` or `UNQLITE_BUSY`.
+///
+/// Every `KV`, `Cursor`, `Transaction`, `Config` and `Jx9` call made through an `UnQLite` handle
+/// (and the `UnQLiteVm`s compiled from it) consults the handle's policy each time the engine
+/// answers with one of these codes. When the policy gives up, the call fails with
+/// `ErrorKind::LOCKED` or `ErrorKind::BUSY`.
+///
+/// ```
+/// # extern crate unqlite;
+/// #
+/// use std::time::Duration;
+/// use unqlite::{BusyPolicy, UnQLite};
+///
+/// # #[cfg(feature = "enable-threads")]
+/// # fn main() {
+/// let unqlite = UnQLite::create_temp()
+///     .busy_policy(BusyPolicy::Timeout(Duration::from_secs(1)));
+///
+/// // Or decide by yourself
+/// let unqlite = UnQLite::create_temp()
+///     .busy_policy(BusyPolicy::handler(|busy| busy.attempts < 8));
+/// # }
+/// # #[cfg(not(feature = "enable-threads"))]
+/// # fn main() { }
+/// ```
+#[derive(Clone)]
+pub enum BusyPolicy {
+    /// Retry `LOCKED` immediately and forever, report `BUSY` at once.
+    ///
+    /// This is the historical behaviour. A lock which is never released makes every call hang.
+    Spin,
+    /// Retry immediately, at most this many times.
+    Retries(u32),
+    /// Sleep between retries, starting at `initial` and doubling up to `max`, at most `retries`
+    /// times.
+    Backoff {
+        initial: Duration,
+        max: Duration,
+        retries: u32,
+    },
+    /// Retry with an exponential backoff (1ms up to 100ms) until this much time has elapsed.
+    Timeout(Duration),
+    /// Let a closure decide: return `true` to retry, `false` to abort.
+    Handler(Arc<dyn Fn(&Busy) -> bool + Send + Sync>),
+}
+
+/// State of a busy operation handed to `BusyPolicy::Handler`.
+#[derive(Clone, Debug)]
+pub struct Busy {
+    /// `ErrorKind::LOCKED` or `ErrorKind::BUSY`
+    pub kind: ErrorKind,
+    /// Number of retries already performed
+    pub attempts: u32,
+    /// Time elapsed since the first attempt
+    pub elapsed: Duration,
+}
+
+impl BusyPolicy {
+    /// Wrap a closure as a `BusyPolicy::Handler`.
+    pub fn handler<F>(f: F) -> Self
+    where
+        F: Fn(&Busy) -> bool + Send + Sync + 'static,
+    {
+        BusyPolicy::Handler(Arc::new(f))
+    }
+
+    /// Run `f` until it returns something else than `LOCKED`/`BUSY` or the policy gives up.
+    pub(crate) fn eval<F: FnMut() -> i32>(&self, mut f: F) -> i32 {
+        let start = Instant::now();
+        let mut attempts = 0;
+        loop {
+            let rc = f();
+            if rc != UNQLITE_LOCKED && rc != UNQLITE_BUSY {
+                return rc;
+            }
+            let busy = Busy {
+                kind: ErrorKind::from(rc),
+                attempts,
+                elapsed: start.elapsed(),
+            };
+            if !self.retry(&busy) {
+                return rc;
+            }
+            attempts += 1;
+        }
+    }
+
+    fn retry(&self, busy: &Busy) -> bool {
+        match *self {
+            BusyPolicy::Spin => busy.kind == ErrorKind::LOCKED,
+            BusyPolicy::Retries(retries) => busy.attempts < retries,
+            BusyPolicy::Backoff {
+                initial,
+                max,
+                retries,
+            } => {
+                if busy.attempts >= retries {
+                    return false;
+                }
+                thread::sleep(backoff(initial, max, busy.attempts));
+                true
+            }
+            BusyPolicy::Timeout(timeout) => {
+                if busy.elapsed >= timeout {
+                    return false;
+                }
+                let delay = backoff(
+                    Duration::from_millis(1),
+                    Duration::from_millis(100),
+                    busy.attempts,
+                );
+                thread::sleep(delay.min(timeout - busy.elapsed));
+                true
+            }
+            BusyPolicy::Handler(ref handler) => handler(busy),
+        }
+    }
+}
+
+/// Five seconds of `BusyPolicy::Timeout`, so a database locked for good fails with
+/// `ErrorKind::LOCKED` or `ErrorKind::BUSY` instead of hanging.
+impl Default for BusyPolicy {
+    fn default() -> Self {
+        BusyPolicy::Timeout(Duration::from_secs(5))
+    }
+}
+
+impl fmt::Debug for BusyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BusyPolicy::Spin => write!(f, "Spin"),
+            BusyPolicy::Retries(retries) => write!(f, "Retries({})", retries),
+            BusyPolicy::Backoff {
+                initial,
+                max,
+                retries,
+            } => f
+                .debug_struct("Backoff")
+                .field("initial", &initial)
+                .field("max", &max)
+                .field("retries", &retries)
+                .finish(),
+            BusyPolicy::Timeout(timeout) => write!(f, "Timeout({:?})", timeout),
+            BusyPolicy::Handler(_) => write!(f, "Handler(..)"),
+        }
+    }
+}
+
+/// `initial * 2^attempts`, capped at `max`.
+fn backoff(initial: Duration, max: Duration, attempts: u32) -> Duration {
+    initial
+        .checked_mul(1u32 << attempts.min(31))
+        .map_or(max, |delay| delay.min(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vars::UNQLITE_OK;
+
+    /// Returns `code` `n` times, then `UNQLITE_OK`.
+    fn busy_for(code: i32, n: u32) -> impl FnMut() -> i32 {
+        let mut calls = 0;
+        move || {
+            calls += 1;
+            if calls > n {
+                UNQLITE_OK
+            } else {
+                code
+            }
+        }
+    }
+
+    #[test]
+    fn spin() {
+        assert_eq!(
+            BusyPolicy::Spin.eval(busy_for(UNQLITE_LOCKED, 100)),
+            UNQLITE_OK
+        );
+        assert_eq!(
+            BusyPolicy::Spin.eval(busy_for(UNQLITE_BUSY, 1)),
+            UNQLITE_BUSY
+        );
+    }
+
+    #[test]
+    fn default_is_bounded() {
+        match BusyPolicy::default() {
+            BusyPolicy::Timeout(timeout) => assert_eq!(timeout, Duration::from_secs(5)),
+            policy => panic!("unexpected default {:?}", policy),
+        }
+    }
+
+    #[test]
+    fn retries() {
+        let policy = BusyPolicy::Retries(3);
+        assert_eq!(policy.eval(busy_for(UNQLITE_LOCKED, 3)), UNQLITE_OK);
+        assert_eq!(policy.eval(busy_for(UNQLITE_LOCKED, 4)), UNQLITE_LOCKED);
+        assert_eq!(policy.eval(busy_for(UNQLITE_BUSY, 4)), UNQLITE_BUSY);
+    }
+
+    #[test]
+    fn backoff_and_timeout() {
+        let policy = BusyPolicy::Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(2),
+            retries: 2,
+        };
+        assert_eq!(policy.eval(busy_for(UNQLITE_BUSY, 2)), UNQLITE_OK);
+        assert_eq!(policy.eval(busy_for(UNQLITE_BUSY, 3)), UNQLITE_BUSY);
+
+        let policy = BusyPolicy::Timeout(Duration::from_millis(20));
+        assert_eq!(policy.eval(busy_for(UNQLITE_LOCKED, 2)), UNQLITE_OK);
+        assert_eq!(
+            policy.eval(busy_for(UNQLITE_LOCKED, u32::MAX)),
+            UNQLITE_LOCKED
+        );
+    }
+
+    #[test]
+    fn handler() {
+        let policy = BusyPolicy::handler(|busy| busy.kind == ErrorKind::BUSY && busy.attempts < 5);
+        assert_eq!(policy.eval(busy_for(UNQLITE_BUSY, 5)), UNQLITE_OK);
+        assert_eq!(policy.eval(busy_for(UNQLITE_BUSY, 6)), UNQLITE_BUSY);
+        assert_eq!(policy.eval(busy_for(UNQLITE_LOCKED, 1)), UNQLITE_LOCKED);
+    }
+
+    #[test]
+    fn backoff_delay() {
+        let ms = Duration::from_millis;
+        assert_eq!(backoff(ms(1), ms(100), 0), ms(1));
+        assert_eq!(backoff(ms(1), ms(100), 3), ms(8));
+        assert_eq!(backoff(ms(1), ms(100), 10), ms(100));
+        assert_eq!(backoff(ms(1), ms(100), 40), ms(100));
+    }
+}
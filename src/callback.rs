@@ -0,0 +1,93 @@
+//! Trampolines running Rust closures as UnQLite consumer callbacks.
+
+use crate::error::{Result, Wrap};
+use crate::vars::{UNQLITE_ABORT, UNQLITE_OK};
+use std::any::Any;
+use std::ops::ControlFlow;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+/// Signature of the consumer callbacks of the C API.
+pub(crate) type RawConsumer = unsafe extern "C" fn(*const c_void, c_uint, *mut c_void) -> c_int;
+
+/// A closure handed to the engine as callback user data.
+pub(crate) struct Consumer<F> {
+    f: F,
+    /// The closure returned `ControlFlow::Break`
+    stopped: bool,
+    /// The closure panicked, to be resumed once back from the engine
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<F: FnMut(&[u8]) -> ControlFlow<()>> Consumer<F> {
+    pub(crate) fn new(f: F) -> Self {
+        Consumer {
+            f,
+            stopped: false,
+            panic: None,
+        }
+    }
+
+    /// The C callback to pass along with `self` as user data.
+    pub(crate) fn raw() -> RawConsumer {
+        trampoline::<F>
+    }
+
+    /// Turn the return code of the engine call into a `Result`.
+    ///
+    /// A panic of the closure is resumed here, and an abort requested by the closure itself is
+    /// not an error.
+    pub(crate) fn finish(&mut self, rc: c_int) -> Result<()> {
+        if let Some(payload) = self.panic.take() {
+            panic::resume_unwind(payload);
+        }
+        if self.stopped && rc == UNQLITE_ABORT {
+            self.stopped = false;
+            return Ok(());
+        }
+        self.stopped = false;
+        rc.wrap()
+    }
+}
+
+/// Run `call` with a C callback consuming data with `f`.
+pub(crate) fn with_consumer<F, C>(f: F, call: C) -> Result<()>
+where
+    F: FnMut(&[u8]) -> ControlFlow<()>,
+    C: FnOnce(RawConsumer, *mut c_void) -> c_int,
+{
+    let mut consumer = Consumer::new(f);
+    let rc = call(
+        Consumer::<F>::raw(),
+        &mut consumer as *mut Consumer<F> as *mut c_void,
+    );
+    consumer.finish(rc)
+}
+
+unsafe extern "C" fn trampoline<F: FnMut(&[u8]) -> ControlFlow<()>>(
+    data: *const c_void,
+    len: c_uint,
+    user_data: *mut c_void,
+) -> c_int {
+    let consumer = &mut *(user_data as *mut Consumer<F>);
+    if consumer.panic.is_some() {
+        return UNQLITE_ABORT;
+    }
+    let data = if len == 0 || data.is_null() {
+        &[][..]
+    } else {
+        slice::from_raw_parts(data as *const u8, len as usize)
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| (consumer.f)(data))) {
+        Ok(ControlFlow::Continue(())) => UNQLITE_OK,
+        Ok(ControlFlow::Break(())) => {
+            consumer.stopped = true;
+            UNQLITE_ABORT
+        }
+        Err(payload) => {
+            consumer.panic = Some(payload);
+            UNQLITE_ABORT
+        }
+    }
+}
@@ -2,7 +2,6 @@ use crate::error::Wrap;
 use crate::ffi::unqlite_config;
 use libc::strlen;
 use std::ffi::CString;
-use std::mem;
 use std::os::raw::c_char;
 use std::ptr;
 use crate::vars::{
@@ -57,9 +56,12 @@ pub trait Config {
 
     /// Switch to another Key/Value storage engine.
     ///
-    /// Offical document says: *This option is reserved for future usage.*
+    /// `name` is either a built-in engine or one installed with
+    /// [`engine::register`](engine/fn.register.html).
     ///
-    /// There is some unknown bug in setting this, **DO NOT** use this option currently.
+    /// ## Panics
+    ///
+    /// Panics if there is no such engine or the database cannot switch to it.
     fn kv_engine<S: Into<Vec<u8>>>(self, name: S) -> Self;
 
     /// The database error log is stored in an internal buffer. When something goes wrong during a
@@ -97,10 +99,10 @@ impl Config for UnQLite {
     }
 
     fn err_log(&self) -> Option<String> {
-        let log: *mut c_char = unsafe { mem::MaybeUninit::uninit().assume_init() };
-        let len: i32 = unsafe { mem::MaybeUninit::uninit().assume_init() };
+        let mut log: *mut c_char = ptr::null_mut();
+        let mut len: i32 = 0;
 
-        wrap_raw!(self, config, UNQLITE_CONFIG_ERR_LOG, &log, &len)
+        wrap_raw!(self, config, UNQLITE_CONFIG_ERR_LOG, &mut log, &mut len)
             .ok()
             .and_then(|_| {
                 if len > 0 {
@@ -112,9 +114,9 @@ impl Config for UnQLite {
     }
 
     fn jx9_err_log(&self) -> Option<String> {
-        let log: *mut c_char = unsafe { mem::MaybeUninit::uninit().assume_init() };
-        let len: i32 = unsafe { mem::MaybeUninit::uninit().assume_init() };
-        wrap_raw!(self, config, UNQLITE_CONFIG_JX9_ERR_LOG, &log, &len)
+        let mut log: *mut c_char = ptr::null_mut();
+        let mut len: i32 = 0;
+        wrap_raw!(self, config, UNQLITE_CONFIG_JX9_ERR_LOG, &mut log, &mut len)
             .ok()
             .and_then(|_| {
                 if len > 0 {
@@ -126,9 +128,9 @@ impl Config for UnQLite {
     }
 
     fn kv_name(&self) -> String {
-        let kv_name: *mut c_char = unsafe { mem::MaybeUninit::uninit().assume_init() };
+        let mut kv_name: *mut c_char = ptr::null_mut();
 
-        wrap_raw!(self, config, UNQLITE_CONFIG_GET_KV_NAME, &kv_name).unwrap();
+        wrap_raw!(self, config, UNQLITE_CONFIG_GET_KV_NAME, &mut kv_name).unwrap();
         from_chars_to_string(kv_name)
     }
 }
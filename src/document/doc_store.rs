@@ -1,4 +1,6 @@
 use super::vm_value::{to_value, Value};
+use crate::busy::BusyPolicy;
+use crate::callback::{with_consumer, Consumer, RawConsumer};
 use crate::error::{Result, Wrap};
 use crate::ffi::{
     unqlite_array_add_strkey_elem, unqlite_compile, unqlite_compile_file, unqlite_value,
@@ -7,8 +9,8 @@ use crate::ffi::{
     unqlite_vm_extract_variable, unqlite_vm_new_array, unqlite_vm_new_scalar, unqlite_vm_release,
     unqlite_vm_release_value, unqlite_vm_reset,
 };
-use std::cell::RefCell;
 use std::ffi::CString;
+use std::ops::ControlFlow;
 use std::os::raw::c_void;
 use std::ptr::{null, null_mut, NonNull};
 use std::rc::Rc;
@@ -42,14 +44,15 @@ impl Jx9 for UnQLite {
         let mut vm: *mut unqlite_vm = null_mut();
         let jx9 = jx9.as_ref();
         wrap_raw!(self, compile, jx9.as_ptr() as _, jx9.len() as _, &mut vm)
-            .map(|_| UnQLiteVm::new(vm))
+            .map(|_| UnQLiteVm::new(vm, self.busy().clone()))
     }
 
     /// Compile a Jx9 script file to a bytecode program.
     fn compile_file<P: AsRef<str>>(&self, filename: P) -> Result<UnQLiteVm> {
         let mut vm: *mut unqlite_vm = null_mut();
         let filename = CString::new(filename.as_ref())?;
-        wrap_raw!(self, compile_file, filename.as_ptr(), &mut vm).map(|_| UnQLiteVm::new(vm))
+        wrap_raw!(self, compile_file, filename.as_ptr(), &mut vm)
+            .map(|_| UnQLiteVm::new(vm, self.busy().clone()))
     }
 }
 
@@ -58,22 +61,24 @@ impl Jx9 for UnQLite {
 /// Wrapper for native [`unqlite_vm`](https://unqlite.org/c_api_object.html#unqlite_vm) structure,
 /// related [functions](https://unqlite.org/c_api_func.html)
 /// and [configuration](https://unqlite.org/c_api/unqlite_vm_config.html).
-#[derive(Clone)]
 pub struct UnQLiteVm {
     native: NonNull<unqlite_vm>,
     executed: bool,
-    output: Option<RefCell<Sender>>,
+    /// Boxed so that its address, handed to the engine, stays the same
+    output: Option<Box<Output>>,
     names: Vec<Rc<CString>>,
+    busy: BusyPolicy,
 }
 
 /// Virtual Machine Object configuration and execution
 impl UnQLiteVm {
-    fn new(vm: *mut unqlite_vm) -> Self {
+    fn new(vm: *mut unqlite_vm, busy: BusyPolicy) -> Self {
         UnQLiteVm {
             native: unsafe { NonNull::new_unchecked(vm) },
             executed: false,
             output: None,
             names: Vec::new(),
+            busy,
         }
     }
 
@@ -91,7 +96,12 @@ impl UnQLiteVm {
         if self.executed {
             self.reset()?
         }
-        wrap_raw!(self, vm_exec).map(|_| self.executed = true)
+        let rc = eval_with!(self.busy(), vm_exec, self.as_raw_mut_ptr());
+        match self.output {
+            Some(ref mut output) => output.finish(rc),
+            None => rc.wrap(),
+        }
+        .map(|_| self.executed = true)
     }
 
     /// Reset a UnQLite virtual machine to its initial state.
@@ -101,12 +111,39 @@ impl UnQLiteVm {
 
     /// Dump Jx9 virtual machine instructions to `stdout`.
     pub fn dump(&self) -> Result<()> {
+        self.dump_to(print_to_stdout)
+    }
+
+    /// Dump Jx9 virtual machine instructions to a closure.
+    pub fn dump_to<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, consumer: F) -> Result<()> {
+        with_consumer(consumer, |raw, data| {
+            eval_with!(self.busy(), vm_dump, self.as_raw_mut_ptr(), Some(raw), data)
+        })
+    }
+
+    /// Redirect VM output to a closure.
+    ///
+    /// Return `ControlFlow::Break(())` to terminate the Jx9 program at this point, which is not
+    /// reported as an error. A panic in `consumer` is resumed when `exec()` returns.
+    ///
+    /// `consumer` must be `Send` as the VM may be executed from another thread.
+    ///
+    /// Should be called before `exec()` method.
+    pub fn output_to<F>(&mut self, consumer: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> ControlFlow<()> + Send + 'static,
+    {
+        let mut output = Box::new(Consumer::new(Box::new(consumer) as OutputFn));
         wrap_raw!(
             self,
-            vm_dump,
-            Some(callback_to_stdout),
-            null_mut::<c_void>()
-        )
+            vm_config,
+            UNQLITE_VM_CONFIG_OUTPUT,
+            Consumer::<OutputFn>::raw(),
+            &mut *output as *mut Output as *mut c_void
+        )?;
+        // Keep the consumer alive as long as the engine may call it.
+        self.output = Some(output);
+        Ok(())
     }
 
     /// Redirect VM output to `std::sync::mpsc::Sender<Vec<u8>>` and
@@ -115,16 +152,12 @@ impl UnQLiteVm {
     /// Should be called before `exec()` method.
     pub fn output_to_channel(&mut self) -> Result<mpsc::Receiver<Vec<u8>>> {
         let (sender, receiver) = mpsc::channel();
-        self.output = Some(RefCell::new(sender));
-        let sender_ptr = self.output.as_ref().unwrap().as_ptr();
-
-        wrap_raw!(
-            self,
-            vm_config,
-            UNQLITE_VM_CONFIG_OUTPUT,
-            callback_to_channel as OutputCallback,
-            sender_ptr
-        )
+        // If the receiver is disconnected, data will never be received:
+        // just continue the Jx9 program.
+        self.output_to(move |data| {
+            let _ = sender.send(data.to_vec());
+            ControlFlow::Continue(())
+        })
         .map(|_| receiver)
     }
 
@@ -135,7 +168,7 @@ impl UnQLiteVm {
             self,
             vm_config,
             UNQLITE_VM_CONFIG_OUTPUT,
-            callback_to_stdout as OutputCallback,
+            callback_to_stdout as RawConsumer,
             null_mut::<c_void>()
         )
     }
@@ -144,17 +177,20 @@ impl UnQLiteVm {
     /// then the Virtual Machine will automatically redirect its output to an internal buffer.
     /// Should be called after `exec()` method.
     pub fn extract_output(&self) -> Result<&[u8]> {
-        let ptr: *const c_void = null();
+        let mut ptr: *const c_void = null();
         let mut len: u32 = 0;
 
         wrap_raw!(
             self,
             vm_config,
             UNQLITE_VM_CONFIG_EXTRACT_OUTPUT,
-            &ptr,
+            &mut ptr,
             &mut len
         )
-        .map(|_| unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) })
+        .map(|_| match len {
+            0 => &[][..],
+            _ => unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) },
+        })
     }
 
     /// Return the total number of bytes that have been outputted by the Virtual Machine
@@ -302,6 +338,11 @@ impl UnQLiteVm {
     unsafe fn as_raw_mut_ptr(&self) -> *mut unqlite_vm {
         self.native.as_ptr()
     }
+
+    /// Busy policy inherited from the `UnQLite` handle which compiled this VM.
+    fn busy(&self) -> &BusyPolicy {
+        &self.busy
+    }
 }
 
 unsafe impl Send for UnQLiteVm {}
@@ -316,31 +357,16 @@ impl Drop for UnQLiteVm {
     }
 }
 
-type Sender = mpsc::Sender<Vec<u8>>;
-type OutputCallback = extern "C" fn(*const c_void, u32, *mut c_void) -> i32;
-
-/// VM output consumer callback.
-/// Should return `UNQLITE_ABORT` or `UNQLITE_OK`.
-/// If `UNQLITE_ABORT` returned then the Jx9 program will be terminated at this point.
-extern "C" fn callback_to_channel(data: *const c_void, len: u32, sender: *mut c_void) -> i32 {
-    let slice: &[u8] = unsafe { slice::from_raw_parts(data as *const u8, len as usize) };
-    let sender: &Sender = unsafe { &*(sender as *mut Sender) };
-
-    let mut msg = Vec::with_capacity(len as usize);
-    msg.extend_from_slice(slice);
+type OutputFn = Box<dyn FnMut(&[u8]) -> ControlFlow<()> + Send>;
+type Output = Consumer<OutputFn>;
 
-    match sender.send(msg) {
-        Ok(_) => UNQLITE_OK,
-
-        // This error means that the channel receiver is disconnected and
-        // data will never be received.
-        // So just continue the Jx9 program.
-        Err(_) => UNQLITE_OK,
-    }
+fn print_to_stdout(data: &[u8]) -> ControlFlow<()> {
+    print!("{}", String::from_utf8_lossy(data));
+    ControlFlow::Continue(())
 }
 
-extern "C" fn callback_to_stdout(data: *const c_void, len: u32, _: *mut c_void) -> i32 {
-    let slice = unsafe { slice::from_raw_parts(data as *const u8, len as usize) };
+unsafe extern "C" fn callback_to_stdout(data: *const c_void, len: u32, _: *mut c_void) -> i32 {
+    let slice = slice::from_raw_parts(data as *const u8, len as usize);
     print!("{}", String::from_utf8_lossy(slice));
     UNQLITE_OK
 }
@@ -353,10 +379,7 @@ struct RawValue {
 
 impl RawValue {
     pub fn new(host: *mut unqlite_vm, raw: *mut unqlite_value) -> Self {
-        RawValue {
-            host: host,
-            raw: raw,
-        }
+        RawValue { host, raw }
     }
 
     pub fn as_ptr(&self) -> *mut unqlite_value {
@@ -1,5 +1,7 @@
 use crate::document::Jx9;
 use crate::document::{Map, Value};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use crate::UnQLite;
 
@@ -29,6 +31,45 @@ fn dump_vm_instructions() {
     db.compile(prog).and_then(|vm| vm.dump()).unwrap();
 }
 
+#[test]
+fn output_closure() {
+    let db = UnQLite::create_in_memory();
+    let prog = "print 'hello'; print 'world';";
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let buf = output.clone();
+    db.compile(prog)
+        .and_then(|mut vm| {
+            vm.output_to(move |data| {
+                buf.lock().unwrap().push(data.to_vec());
+                ControlFlow::Break(())
+            })?;
+            vm.exec_void()
+        })
+        .unwrap();
+    assert_eq!(*output.lock().unwrap(), vec![b"hello".to_vec()]);
+
+    let mut instructions = 0;
+    db.compile(prog)
+        .and_then(|vm| {
+            vm.dump_to(|data| {
+                instructions += data.len();
+                ControlFlow::Continue(())
+            })
+        })
+        .unwrap();
+    assert!(instructions > 0);
+}
+
+#[test]
+#[should_panic(expected = "output panic")]
+fn output_closure_panic() {
+    let db = UnQLite::create_in_memory();
+    let mut vm = db.compile("print 'hello';").unwrap();
+    vm.output_to(|_| panic!("output panic")).unwrap();
+    let _ = vm.exec_void();
+}
+
 #[test]
 fn output_channel() {
     let db = UnQLite::create_in_memory();
@@ -173,10 +214,10 @@ fn exec_result() {
                         Value::Array(vec![Value::Int(1), Value::Int(2), Value::Int(3)]),
                         map["key_3"]
                     );
-                    if let &Value::Object(ref sub_map) = &map["key_4"] {
+                    if let Value::Object(sub_map) = &map["key_4"] {
                         assert_eq!(Value::Int(42), sub_map["sub_key2"]);
                     } else {
-                        assert!(false);
+                        panic!("key_4 is not an object");
                     }
                 }
             })
@@ -211,7 +252,7 @@ fn add_extract_variable() {
             vm.add_variable("map2", Value::Object(map2))?;
             vm.add_variable("arr1", Value::Array(vec![Value::Null, Value::Int(100)]))?;
             vm.add_variable("val6", Value::Int(10))?;
-            vm.exec().and_then(|result| {
+            vm.exec().map(|result| {
                 assert_eq!(Some(Value::string("pos")), result);
                 assert_eq!(Some(Value::Int(10)), vm.extract_variable("val1"));
                 assert_eq!(Some(Value::string("test_mod")), vm.extract_variable("val2"));
@@ -238,8 +279,6 @@ fn add_extract_variable() {
                 let arr2 = arr2.unwrap();
                 assert_eq!(3, arr2.len());
                 assert_eq!(Value::string("new_elem"), arr2[2]);
-
-                Ok(())
             })
         })
         .unwrap()
@@ -31,10 +31,7 @@ impl Value {
     }
 
     pub fn is_scalar(&self) -> bool {
-        match *self {
-            Value::Array(_) | Value::Object(_) => false,
-            _ => true,
-        }
+        !matches!(*self, Value::Array(_) | Value::Object(_))
     }
 }
 
@@ -77,7 +74,7 @@ pub unsafe fn to_value(ptr: *mut unqlite_value) -> Option<Value> {
     } else if unqlite_value_is_int(ptr) == TRUE {
         Some(Value::Int(unqlite_value_to_int64(ptr) as i64))
     } else if unqlite_value_is_float(ptr) == TRUE {
-        Some(Value::Real(unqlite_value_to_double(ptr) as f64))
+        Some(Value::Real(unqlite_value_to_double(ptr)))
     } else if unqlite_value_is_string(ptr) == TRUE {
         let string = value_to_string(ptr);
         debug_assert!(string.is_some());
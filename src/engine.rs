@@ -0,0 +1,523 @@
+//! Key/Value storage engines written in Rust.
+//!
+//! UnQLite ships with its own storage engines (`hash` for on-disk and `mem` for in-memory
+//! databases). [`register`](fn.register.html) adds an engine implemented with the
+//! [`StorageEngine`](trait.StorageEngine.html) trait, which databases can then switch to with
+//! [`Config::kv_engine`](../trait.Config.html#tymethod.kv_engine):
+//!
+//! ```no_run
+//! # extern crate unqlite;
+//! #
+//! use unqlite::engine::{self, BTreeEngine};
+//! use unqlite::{Config, Cursor, UnQLite, KV};
+//!
+//! # fn main() {
+//! // Must happen before the first database is opened.
+//! engine::register("btree", BTreeEngine::default).unwrap();
+//!
+//! let unqlite = UnQLite::create_in_memory().kv_engine("btree");
+//! unqlite.kv_store("b", "2").unwrap();
+//! unqlite.kv_store("a", "1").unwrap();
+//! assert_eq!(unqlite.first().unwrap().key(), b"a");
+//! # }
+//! ```
+//!
+//! Each database gets its own engine instance built by the registered factory. The engine owns
+//! its records: nothing goes through the pager, so the content is not persisted to the database
+//! file and transactions have no effect on it. Operations promising atomicity, such as
+//! [`WriteBatch::apply`](../struct.WriteBatch.html#method.apply) or
+//! [`KV::kv_delete_range`](../trait.KV.html#tymethod.kv_delete_range), fail with
+//! `ErrorKind::NOTIMPLEMENTED` on such engines, see
+//! [`UnQLite::is_transactional`](../struct.UnQLite.html#method.is_transactional).
+
+use crate::error::{Result, Wrap};
+use crate::ffi::{
+    unqlite_int64, unqlite_kv_cursor, unqlite_kv_engine, unqlite_kv_methods, unqlite_lib_config,
+};
+use crate::vars::{
+    UNQLITE_ABORT, UNQLITE_EOF, UNQLITE_LIB_CONFIG_STORAGE_ENGINE, UNQLITE_NOTFOUND, UNQLITE_OK,
+};
+use crate::Direction;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::mem;
+use std::ops::Bound::{Excluded, Included, Unbounded};
+use std::os::raw::{c_int, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// A Key/Value storage engine.
+///
+/// This mirrors UnQLite's `unqlite_kv_methods`: records are written with `replace`, `append` and
+/// `delete`, while cursors walk the keys in the engine's own order with `seek`, `first`, `last`,
+/// `next` and `prev`.
+pub trait StorageEngine: Send + 'static {
+    /// Store `value` under `key`, overwriting any existing record.
+    fn replace(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Append `value` to the record under `key`, creating it if it does not exist.
+    fn append(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut data = self.fetch(key).map(<[u8]>::to_vec).unwrap_or_default();
+        data.extend_from_slice(value);
+        self.replace(key, &data)
+    }
+
+    /// Returns the value stored under `key`.
+    fn fetch(&self, key: &[u8]) -> Option<&[u8]>;
+
+    /// Remove the record under `key`.
+    fn delete(&mut self, key: &[u8]) -> Result<()>;
+
+    /// Find a key as described in [`Cursor::seek`](../trait.Cursor.html#tymethod.seek):
+    /// `key` itself for `Exact`, the largest key lower or equal to `key` for `Le`, the smallest
+    /// key greater or equal to `key` for `Ge`.
+    fn seek(&self, key: &[u8], pos: Direction) -> Option<Vec<u8>>;
+
+    /// Returns the first key.
+    fn first(&self) -> Option<Vec<u8>>;
+
+    /// Returns the last key.
+    fn last(&self) -> Option<Vec<u8>>;
+
+    /// Returns the key following `key`, which may have been deleted meanwhile.
+    fn next(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Returns the key preceding `key`, which may have been deleted meanwhile.
+    fn prev(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Whether `first`, `next`, `last` and `prev` visit keys in bytewise order, with `seek`
+    /// supporting `Le` and `Ge`. This lets [`Cursor::range`](../trait.Cursor.html#tymethod.range)
+    /// seek its bounds instead of scanning every record.
+    fn is_ordered(&self) -> bool {
+        false
+    }
+}
+
+/// An ordered engine on top of a `BTreeMap`, keys are sorted bytewise.
+#[derive(Clone, Debug, Default)]
+pub struct BTreeEngine {
+    records: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl StorageEngine for BTreeEngine {
+    fn replace(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.records.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn append(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.records
+            .entry(key.to_vec())
+            .or_default()
+            .extend_from_slice(value);
+        Ok(())
+    }
+
+    fn fetch(&self, key: &[u8]) -> Option<&[u8]> {
+        self.records.get(key).map(Vec::as_slice)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.records
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| crate::ErrorKind::NOTFOUND.into())
+    }
+
+    fn seek(&self, key: &[u8], pos: Direction) -> Option<Vec<u8>> {
+        let found = match pos {
+            Direction::Exact => self.records.get_key_value(key),
+            Direction::Le => self
+                .records
+                .range::<[u8], _>((Unbounded, Included(key)))
+                .next_back(),
+            Direction::Ge => self
+                .records
+                .range::<[u8], _>((Included(key), Unbounded))
+                .next(),
+        };
+        found.map(|(key, _)| key.clone())
+    }
+
+    fn first(&self) -> Option<Vec<u8>> {
+        self.records.keys().next().cloned()
+    }
+
+    fn last(&self) -> Option<Vec<u8>> {
+        self.records.keys().next_back().cloned()
+    }
+
+    fn next(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.records
+            .range::<[u8], _>((Excluded(key), Unbounded))
+            .next()
+            .map(|(key, _)| key.clone())
+    }
+
+    fn prev(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.records
+            .range::<[u8], _>((Unbounded, Excluded(key)))
+            .next_back()
+            .map(|(key, _)| key.clone())
+    }
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+}
+
+/// Register a storage engine under `name`.
+///
+/// `factory` is called each time a database switches to the engine. This must be called before
+/// the first database is opened, otherwise the library is already initialized and
+/// `ErrorKind::LOCKED` is returned.
+pub fn register<F, E>(name: &str, factory: F) -> Result<()>
+where
+    F: Fn() -> E + Send + Sync + 'static,
+    E: StorageEngine,
+{
+    let name = CString::new(name)?;
+    let raw = Box::into_raw(Box::new(RawMethods {
+        base: unqlite_kv_methods {
+            zName: name.as_ptr(),
+            szKv: mem::size_of::<RawEngine>() as c_int,
+            szCursor: mem::size_of::<RawCursor>() as c_int,
+            iVersion: 1,
+            xInit: Some(engine_init),
+            xRelease: Some(engine_release),
+            xConfig: None,
+            xOpen: Some(engine_open),
+            xReplace: Some(engine_replace),
+            xAppend: Some(engine_append),
+            xCursorInit: Some(cursor_init),
+            xSeek: Some(cursor_seek),
+            xFirst: Some(cursor_first),
+            xLast: Some(cursor_last),
+            xValid: Some(cursor_valid),
+            xNext: Some(cursor_next),
+            xPrev: Some(cursor_prev),
+            xDelete: Some(cursor_delete),
+            xKeyLength: Some(cursor_key_length),
+            xKey: Some(cursor_key),
+            xDataLength: Some(cursor_data_length),
+            xData: Some(cursor_data),
+            xReset: Some(cursor_reset),
+            xCursorRelease: Some(cursor_release),
+        },
+        factory: Box::new(move || Box::new(factory()) as Box<dyn StorageEngine>),
+        name,
+    }));
+    // The engine keeps the pointer forever: leak it on success.
+    unsafe {
+        unqlite_lib_config(
+            UNQLITE_LIB_CONFIG_STORAGE_ENGINE,
+            raw as *mut unqlite_kv_methods,
+        )
+    }
+    .wrap()
+    .inspect_err(|_| drop(unsafe { Box::from_raw(raw) }))
+}
+
+type Factory = Box<dyn Fn() -> Box<dyn StorageEngine> + Send + Sync>;
+
+/// `unqlite_kv_methods` followed by the factory, reachable from every engine through `pIo`.
+#[repr(C)]
+struct RawMethods {
+    base: unqlite_kv_methods,
+    factory: Factory,
+    /// Owns `base.zName`
+    name: CString,
+}
+
+/// `unqlite_kv_engine` subclass, allocated by the engine with `szKv` bytes.
+#[repr(C)]
+struct RawEngine {
+    base: unqlite_kv_engine,
+    engine: Box<dyn StorageEngine>,
+    /// Whether `engine` was written, the engine is zeroed before `xInit` and `xRelease` is
+    /// called even if the factory panicked
+    initialized: bool,
+}
+
+/// `unqlite_kv_cursor` subclass, allocated by the engine with `szCursor` bytes.
+#[repr(C)]
+struct RawCursor {
+    base: unqlite_kv_cursor,
+    /// The key pointed to, `None` once past either end
+    key: Option<Vec<u8>>,
+}
+
+type Consumer = Option<unsafe extern "C" fn(*const c_void, c_uint, *mut c_void) -> c_int>;
+
+/// Run `f`, turning panics into `UNQLITE_ABORT`.
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(UNQLITE_ABORT)
+}
+
+fn result_code(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => UNQLITE_OK,
+        Err(err) => err.code(),
+    }
+}
+
+unsafe fn engine<'a>(raw: *mut unqlite_kv_engine) -> &'a mut dyn StorageEngine {
+    &mut *(*(raw as *mut RawEngine)).engine
+}
+
+unsafe fn cursor<'a>(
+    raw: *mut unqlite_kv_cursor,
+) -> (&'a mut Option<Vec<u8>>, &'a mut dyn StorageEngine) {
+    (&mut (*(raw as *mut RawCursor)).key, engine((*raw).pStore))
+}
+
+/// Whether `raw` is an engine registered by `register`.
+pub(crate) unsafe fn is_registered(raw: *mut unqlite_kv_engine) -> bool {
+    let methods = (*(*raw).pIo).pMethods;
+    // Only engines registered by `register` start with `engine_init`.
+    let init = engine_init as unsafe extern "C" fn(_, _) -> _;
+    (*methods).xInit.map(|f| f as usize) == Some(init as usize)
+}
+
+/// Whether `raw` is a Rust engine visiting keys in order.
+pub(crate) unsafe fn is_ordered(raw: *mut unqlite_kv_engine) -> bool {
+    is_registered(raw) && engine(raw).is_ordered()
+}
+
+/// Point the cursor to `key`, `rc` is returned if there is none.
+fn move_to(cursor: &mut Option<Vec<u8>>, key: Option<Vec<u8>>, rc: c_int) -> c_int {
+    *cursor = key;
+    if cursor.is_some() {
+        UNQLITE_OK
+    } else {
+        rc
+    }
+}
+
+unsafe fn bytes<'a>(ptr: *const c_void, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr as *const u8, len)
+    }
+}
+
+unsafe fn consume(data: &[u8], consumer: Consumer, user_data: *mut c_void) -> c_int {
+    match consumer {
+        Some(consumer) => {
+            if consumer(
+                data.as_ptr() as *const c_void,
+                data.len() as c_uint,
+                user_data,
+            ) == 0
+            {
+                UNQLITE_OK
+            } else {
+                UNQLITE_ABORT
+            }
+        }
+        None => UNQLITE_OK,
+    }
+}
+
+unsafe extern "C" fn engine_init(raw: *mut unqlite_kv_engine, _page_size: c_int) -> c_int {
+    let methods = (*(*raw).pIo).pMethods as *const RawMethods;
+    guard(|| {
+        let engine = ((*methods).factory)();
+        ptr::write(&mut (*(raw as *mut RawEngine)).engine, engine);
+        (*(raw as *mut RawEngine)).initialized = true;
+        UNQLITE_OK
+    })
+}
+
+unsafe extern "C" fn engine_release(raw: *mut unqlite_kv_engine) {
+    let raw = raw as *mut RawEngine;
+    if (*raw).initialized {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| ptr::drop_in_place(&mut (*raw).engine)));
+    }
+}
+
+unsafe extern "C" fn engine_open(_raw: *mut unqlite_kv_engine, _root: u64) -> c_int {
+    UNQLITE_OK
+}
+
+unsafe extern "C" fn engine_replace(
+    raw: *mut unqlite_kv_engine,
+    key: *const c_void,
+    key_len: c_int,
+    data: *const c_void,
+    data_len: unqlite_int64,
+) -> c_int {
+    guard(|| {
+        result_code(
+            engine(raw).replace(bytes(key, key_len as usize), bytes(data, data_len as usize)),
+        )
+    })
+}
+
+unsafe extern "C" fn engine_append(
+    raw: *mut unqlite_kv_engine,
+    key: *const c_void,
+    key_len: c_int,
+    data: *const c_void,
+    data_len: unqlite_int64,
+) -> c_int {
+    guard(|| {
+        result_code(
+            engine(raw).append(bytes(key, key_len as usize), bytes(data, data_len as usize)),
+        )
+    })
+}
+
+unsafe extern "C" fn cursor_init(raw: *mut unqlite_kv_cursor) {
+    ptr::write(&mut (*(raw as *mut RawCursor)).key, None);
+}
+
+unsafe extern "C" fn cursor_seek(
+    raw: *mut unqlite_kv_cursor,
+    key: *const c_void,
+    key_len: c_int,
+    pos: c_int,
+) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| {
+        let found = engine.seek(bytes(key, key_len as usize), Direction::from_raw(pos));
+        move_to(cursor, found, UNQLITE_NOTFOUND)
+    })
+}
+
+unsafe extern "C" fn cursor_first(raw: *mut unqlite_kv_cursor) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| move_to(cursor, engine.first(), UNQLITE_EOF))
+}
+
+unsafe extern "C" fn cursor_last(raw: *mut unqlite_kv_cursor) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| move_to(cursor, engine.last(), UNQLITE_EOF))
+}
+
+unsafe extern "C" fn cursor_valid(raw: *mut unqlite_kv_cursor) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| match *cursor {
+        Some(ref key) => engine.fetch(key).is_some() as c_int,
+        None => 0,
+    })
+}
+
+unsafe extern "C" fn cursor_next(raw: *mut unqlite_kv_cursor) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| match cursor.take() {
+        Some(key) => move_to(cursor, engine.next(&key), UNQLITE_EOF),
+        None => UNQLITE_EOF,
+    })
+}
+
+unsafe extern "C" fn cursor_prev(raw: *mut unqlite_kv_cursor) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| match cursor.take() {
+        Some(key) => move_to(cursor, engine.prev(&key), UNQLITE_EOF),
+        None => UNQLITE_EOF,
+    })
+}
+
+/// Delete the pointed record and move to the next one.
+unsafe extern "C" fn cursor_delete(raw: *mut unqlite_kv_cursor) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| match cursor.take() {
+        Some(key) => {
+            let rc = result_code(engine.delete(&key));
+            *cursor = engine.next(&key);
+            rc
+        }
+        None => UNQLITE_EOF,
+    })
+}
+
+unsafe extern "C" fn cursor_key_length(raw: *mut unqlite_kv_cursor, len: *mut c_int) -> c_int {
+    let (cursor, _) = cursor(raw);
+    match *cursor {
+        Some(ref key) => {
+            *len = key.len() as c_int;
+            UNQLITE_OK
+        }
+        None => UNQLITE_EOF,
+    }
+}
+
+unsafe extern "C" fn cursor_key(
+    raw: *mut unqlite_kv_cursor,
+    consumer: Consumer,
+    user_data: *mut c_void,
+) -> c_int {
+    let (cursor, _) = cursor(raw);
+    guard(|| match *cursor {
+        Some(ref key) => consume(key, consumer, user_data),
+        None => UNQLITE_EOF,
+    })
+}
+
+unsafe extern "C" fn cursor_data_length(
+    raw: *mut unqlite_kv_cursor,
+    len: *mut unqlite_int64,
+) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| match cursor.as_ref().and_then(|key| engine.fetch(key)) {
+        Some(data) => {
+            *len = data.len() as unqlite_int64;
+            UNQLITE_OK
+        }
+        None => UNQLITE_NOTFOUND,
+    })
+}
+
+unsafe extern "C" fn cursor_data(
+    raw: *mut unqlite_kv_cursor,
+    consumer: Consumer,
+    user_data: *mut c_void,
+) -> c_int {
+    let (cursor, engine) = cursor(raw);
+    guard(|| match cursor.as_ref().and_then(|key| engine.fetch(key)) {
+        Some(data) => consume(data, consumer, user_data),
+        None => UNQLITE_NOTFOUND,
+    })
+}
+
+unsafe extern "C" fn cursor_reset(raw: *mut unqlite_kv_cursor) {
+    *cursor(raw).0 = None;
+}
+
+unsafe extern "C" fn cursor_release(raw: *mut unqlite_kv_cursor) {
+    ptr::drop_in_place(&mut (*(raw as *mut RawCursor)).key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BTreeEngine, StorageEngine};
+    use crate::Direction;
+
+    #[test]
+    fn btree_engine() {
+        let mut engine = BTreeEngine::default();
+        engine.replace(b"b", b"2").unwrap();
+        engine.replace(b"d", b"4").unwrap();
+        engine.append(b"a", b"1").unwrap();
+        engine.append(b"a", b"1").unwrap();
+        assert_eq!(engine.fetch(b"a"), Some(&b"11"[..]));
+
+        assert_eq!(engine.first(), Some(b"a".to_vec()));
+        assert_eq!(engine.last(), Some(b"d".to_vec()));
+        assert_eq!(engine.next(b"b"), Some(b"d".to_vec()));
+        assert_eq!(engine.next(b"c"), Some(b"d".to_vec()));
+        assert_eq!(engine.prev(b"a"), None);
+
+        assert_eq!(engine.seek(b"c", Direction::Exact), None);
+        assert_eq!(engine.seek(b"c", Direction::Le), Some(b"b".to_vec()));
+        assert_eq!(engine.seek(b"c", Direction::Ge), Some(b"d".to_vec()));
+        assert_eq!(engine.seek(b"d", Direction::Ge), Some(b"d".to_vec()));
+
+        engine.delete(b"b").unwrap();
+        assert!(engine.delete(b"b").is_err());
+        assert_eq!(engine.next(b"a"), Some(b"d".to_vec()));
+    }
+}
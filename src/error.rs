@@ -30,6 +30,86 @@ impl From<::std::ffi::NulError> for Error {
     }
 }
 
+impl From<::std::string::FromUtf8Error> for Error {
+    fn from(err: ::std::string::FromUtf8Error) -> Error {
+        Error::Other(Box::new(err))
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Other(Box::new(err))
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Error {
+        Error::Other(err)
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl From<ciborium::ser::Error<::std::io::Error>> for Error {
+    fn from(err: ciborium::ser::Error<::std::io::Error>) -> Error {
+        Error::Other(Box::new(err))
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl From<ciborium::de::Error<::std::io::Error>> for Error {
+    fn from(err: ciborium::de::Error<::std::io::Error>) -> Error {
+        Error::Other(Box::new(err))
+    }
+}
+
+impl From<Error> for ::std::io::Error {
+    // `io::Error::other` needs Rust 1.74.
+    #[allow(clippy::io_other_error)]
+    fn from(err: Error) -> ::std::io::Error {
+        use std::io;
+        match err {
+            Error::Custom(c) => {
+                let kind = match c.kind {
+                    ErrorKind::NOTFOUND => io::ErrorKind::NotFound,
+                    ErrorKind::PERM => io::ErrorKind::PermissionDenied,
+                    _ => io::ErrorKind::Other,
+                };
+                io::Error::new(kind, c)
+            }
+            Error::Other(e) => io::Error::new(io::ErrorKind::Other, e.to_string()),
+        }
+    }
+}
+
+impl Error {
+    /// Returns the UnQLite error kind, or `None` for errors from other sources.
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match *self {
+            Error::Custom(ref c) => Some(c.kind()),
+            Error::Other(_) => None,
+        }
+    }
+
+    /// The UnQLite return code to hand back to the engine for this error.
+    pub(crate) fn code(&self) -> i32 {
+        match *self {
+            Error::Custom(ref c) => c.raw,
+            Error::Other(_) => UNQLITE_IOERR,
+        }
+    }
+
+    /// Build an error from a raw UnQLite return code.
+    pub(crate) fn from_code(code: i32) -> Error {
+        Custom {
+            kind: ErrorKind::from(code),
+            raw: code,
+        }
+        .into()
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -47,7 +127,7 @@ pub struct Custom {
 
 /// Error kinds from unqlite official documents.
 #[allow(non_camel_case_types)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
     /// Successful result
     OK = 0,
@@ -133,6 +213,45 @@ impl From<i32> for ErrorKind {
     }
 }
 
+impl ErrorKind {
+    /// The raw UnQLite return code of this kind.
+    fn code(self) -> i32 {
+        match self {
+            ErrorKind::OK => UNQLITE_OK,
+            ErrorKind::NOMEM => UNQLITE_NOMEM,
+            ErrorKind::ABORT => UNQLITE_ABORT,
+            ErrorKind::IOERR => UNQLITE_IOERR,
+            ErrorKind::CORRUPT => UNQLITE_CORRUPT,
+            ErrorKind::LOCKED => UNQLITE_LOCKED,
+            ErrorKind::BUSY => UNQLITE_BUSY,
+            ErrorKind::DONE => UNQLITE_DONE,
+            ErrorKind::PERM => UNQLITE_PERM,
+            ErrorKind::NOTIMPLEMENTED => UNQLITE_NOTIMPLEMENTED,
+            ErrorKind::NOTFOUND => UNQLITE_NOTFOUND,
+            ErrorKind::NOOP => UNQLITE_NOOP,
+            ErrorKind::INVALID => UNQLITE_INVALID,
+            ErrorKind::EOF => UNQLITE_EOF,
+            ErrorKind::UNKNOWN => UNQLITE_UNKNOWN,
+            ErrorKind::LIMIT => UNQLITE_LIMIT,
+            ErrorKind::EXISTS => UNQLITE_EXISTS,
+            ErrorKind::EMPTY => UNQLITE_EMPTY,
+            ErrorKind::COMPILE_ERR => UNQLITE_COMPILE_ERR,
+            ErrorKind::VM_ERR => UNQLITE_VM_ERR,
+            ErrorKind::FULL => UNQLITE_FULL,
+            ErrorKind::CANTOPEN => UNQLITE_CANTOPEN,
+            ErrorKind::READ_ONLY => UNQLITE_READ_ONLY,
+            ErrorKind::LOCKERR => UNQLITE_LOCKERR,
+            ErrorKind::__Nonexhaustive => UNQLITE_UNKNOWN,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error::from_code(kind.code())
+    }
+}
+
 /// A wrap trait for unqlite FFI error code to Rust-y `Result`.
 ///
 /// To populate better visual style, we add a `Wrap` trait to original
@@ -163,13 +282,23 @@ impl Custom {
         match kind {
             ErrorKind::OK => Ok(()),
             _ => Err(Custom {
-                kind: kind,
+                kind,
                 raw: result,
             }
             .into()),
         }
     }
 
+    /// Returns the error kind.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the raw UnQLite return code.
+    pub fn raw(&self) -> i32 {
+        self.raw
+    }
+
     pub fn error(&self) -> &str {
         match self.kind {
             ErrorKind::NOMEM => "Out of memory",
@@ -1,4 +1,5 @@
-use crate::error::{Result, Wrap};
+use crate::callback::with_consumer;
+use crate::error::{ErrorKind, Result, Wrap};
 use crate::ffi::{
     unqlite, unqlite_kv_cursor, unqlite_kv_cursor_data, unqlite_kv_cursor_data_callback,
     unqlite_kv_cursor_delete_entry, unqlite_kv_cursor_first_entry, unqlite_kv_cursor_init,
@@ -6,11 +7,13 @@ use crate::ffi::{
     unqlite_kv_cursor_next_entry, unqlite_kv_cursor_prev_entry, unqlite_kv_cursor_release,
     unqlite_kv_cursor_reset, unqlite_kv_cursor_seek, unqlite_kv_cursor_valid_entry,
 };
+use std::iter::FusedIterator;
 use std::mem;
-use std::os::raw::c_void;
+use std::ops::{Bound, ControlFlow, RangeBounds};
 use std::ptr::{self, NonNull};
 use crate::vars::{UNQLITE_CURSOR_MATCH_EXACT, UNQLITE_CURSOR_MATCH_GE, UNQLITE_CURSOR_MATCH_LE};
-use crate::UnQLite;
+use crate::kv_store::RESERVED;
+use crate::{engine, ttl, UnQLite};
 
 /// Cursor iterator interfaces.
 ///
@@ -46,7 +49,8 @@ use crate::UnQLite;
 /// `last`, call `prev` instead of `next()` on `entry`.
 ///
 /// You can also use cursors to search for records and start the iteration process from there.
-/// To do that, start from [`seek`](#tymethod.seek) method.
+/// To do that, start from [`seek`](#tymethod.seek) method. To look up many keys, open a single
+/// cursor with [`cursor`](#tymethod.cursor) and reposition it for each one.
 ///
 /// To retrieve record key/value from a valid cursor, just use like:
 ///
@@ -60,118 +64,722 @@ use crate::UnQLite;
 /// To delete a record from the database using the cursor interface, simply point to the target
 /// record using `seek` and call `delete` on the `Entry` object.
 ///
-/// A rusty `Iterator` style would perform in a short time.
+/// To go through records with iterator adapters, use [`iter`](#tymethod.iter) instead:
+///
+/// ```
+/// # extern crate unqlite;
+/// #
+/// use unqlite::{Cursor, UnQLite, KV};
+/// #
+/// # #[cfg(feature = "enable-threads")]
+/// # fn main() {
+/// let unqlite = UnQLite::create_in_memory();
+/// unqlite.kv_store("key", "value").unwrap();
+///
+/// let records: Vec<_> = unqlite.iter().rev().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(records, [(b"key".to_vec(), b"value".to_vec())]);
+/// # }
+/// # #[cfg(not(feature = "enable-threads"))]
+/// # fn main() { }
+/// ```
 pub trait Cursor {
     /// Returns the first entry.
-    fn first(&self) -> Option<Entry>;
+    fn first(&self) -> Option<Entry<'_>>;
 
     /// Retruns the last entry.
-    fn last(&self) -> Option<Entry>;
+    fn last(&self) -> Option<Entry<'_>>;
 
     /// Seek an entry by `key`.
     ///
     /// The `pos` `Direction` options:
     ///
     ///   * **Exact**: If the record exists, the cursor is left pointing to it,
-    /// otherwise return `None`.
-    ///   * **Le**: The cursor is left pointing to the largest key in the database that is smaller
-    /// than `key`, If the database contains no keys smaller than `key`, it returns `None`.
+    ///     otherwise return `None`.
+    ///   * **Le**: The cursor is left pointing to the largest key in the database that is
+    ///     smaller than `key`, If the database contains no keys smaller than `key`, it returns
+    ///     `None`.
     ///   * **Ge**: Oppsite to **Le**, it returns the smallest `Entry` in the database that is
-    ///   larger than `key`.If the database contains no keys smaller than `key`, return `None`.
-    fn seek<K: AsRef<[u8]>>(&self, key: K, pos: Direction) -> Option<Entry>;
+    ///     larger than `key`.If the database contains no keys smaller than `key`, return `None`.
+    fn seek<K: AsRef<[u8]>>(&self, key: K, pos: Direction) -> Option<Entry<'_>>;
+
+    /// Open a cursor to reposition many times, without initializing a new one for each lookup.
+    ///
+    /// See [`KvCursor`](struct.KvCursor.html).
+    fn cursor(&self) -> Result<KvCursor<'_>>;
+
+    /// Iterate over the key-value pairs, from the first record to the last one, or backwards
+    /// with `rev`.
+    fn iter(&self) -> Iter<'_>;
+
+    /// Iterate over the key-value pairs with a key within `range`, in both directions.
+    ///
+    /// Keys are compared bytewise. Only an ordered engine, such as
+    /// [`BTreeEngine`](engine/struct.BTreeEngine.html), lets the iteration seek the bounds and
+    /// stop past them. The built-in `hash` and `mem` engines visit keys in no particular order:
+    /// the whole database is then scanned and filtered. See
+    /// [`UnQLite::is_ordered`](struct.UnQLite.html#method.is_ordered).
+    ///
+    /// ```ignore
+    /// for record in unqlite.range("b".."d") {
+    ///     let (key, value) = record?;
+    /// }
+    /// ```
+    fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Iter<'_>;
+
+    /// Iterate over the key-value pairs with a key starting with `prefix`.
+    ///
+    /// On an ordered engine, the iteration seeks `prefix` and stops at the first key not
+    /// starting with it. Otherwise, the whole database is scanned, see
+    /// [`range`](#tymethod.range).
+    ///
+    /// ```ignore
+    /// for record in unqlite.scan_prefix("user:42:") {
+    ///     let (key, value) = record?;
+    /// }
+    /// ```
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Iter<'_> {
+        self.range(prefix_range(prefix.as_ref()))
+    }
+
+    /// Iterate over the keys, without reading the values.
+    ///
+    /// Use [`Iter::keys`](struct.Iter.html#method.keys) to list the keys of a range or prefix.
+    fn keys(&self) -> Keys<'_> {
+        self.iter().keys()
+    }
+
+    /// Iterate over the values.
+    fn values(&self) -> Values<'_> {
+        self.iter().values()
+    }
+}
+
+/// The range of the keys starting with `prefix`.
+pub(crate) fn prefix_range(prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    // The first key past the prefix: increment its last byte below `0xff`, dropping the rest.
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return (Bound::Included(prefix.to_vec()), Bound::Excluded(end));
+        }
+    }
+    (Bound::Included(prefix.to_vec()), Bound::Unbounded)
 }
 
 impl Cursor for UnQLite {
-    fn first(&self) -> Option<Entry> {
-        RawCursor::init(self)
-            .and_then(|cur| cur.first())
-            .ok()
-            .and_then(|cur| cur.valid())
-            .map(Entry)
+    fn first(&self) -> Option<Entry<'_>> {
+        Entry::first(self, Vec::new())
+    }
+    fn last(&self) -> Option<Entry<'_>> {
+        Entry::last(self, Vec::new())
     }
-    fn last(&self) -> Option<Entry> {
-        RawCursor::init(self)
-            .and_then(|cur| cur.last())
-            .ok()
-            .and_then(|cur| cur.valid())
-            .map(Entry)
+    fn seek<K: AsRef<[u8]>>(&self, key: K, pos: Direction) -> Option<Entry<'_>> {
+        Entry::seek(self, Vec::new(), key.as_ref(), pos)
     }
-    fn seek<K: AsRef<[u8]>>(&self, key: K, pos: Direction) -> Option<Entry> {
-        RawCursor::init(self)
-            .and_then(|cur| cur.seek(key, pos))
-            .ok()
-            .and_then(|cur| cur.valid())
-            .map(Entry)
+    fn cursor(&self) -> Result<KvCursor<'_>> {
+        KvCursor::open(self, Vec::new())
+    }
+    fn iter(&self) -> Iter<'_> {
+        Iter::new(self, Vec::new())
+    }
+    fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Iter<'_> {
+        Iter::range(self, Vec::new(), range)
+    }
+}
+
+impl UnQLite {
+    /// Whether the active Key/Value engine visits keys in bytewise order, as a
+    /// [`StorageEngine`](engine/trait.StorageEngine.html) telling so with `is_ordered`.
+    ///
+    /// The built-in `hash` and `mem` engines are not ordered.
+    pub fn is_ordered(&self) -> bool {
+        RawCursor::init(self).is_ok_and(|cursor| cursor.is_ordered())
+    }
+
+    /// Whether transactions apply to the active Key/Value engine.
+    ///
+    /// A [`StorageEngine`](engine/trait.StorageEngine.html) owns its records outside of the
+    /// pager, so transactions have no effect on it.
+    pub fn is_transactional(&self) -> bool {
+        RawCursor::init(self).map_or(true, |cursor| cursor.is_transactional())
+    }
+
+    /// Fail with `ErrorKind::NOTIMPLEMENTED` unless the engine is transactional.
+    pub(crate) fn check_transactional(&self) -> Result<()> {
+        if self.is_transactional() {
+            Ok(())
+        } else {
+            Err(ErrorKind::NOTIMPLEMENTED.into())
+        }
     }
 }
 
 /// A valid cursor entry of record.
-pub struct Entry(RawCursor);
+///
+/// An entry borrows its database, which cannot be closed while the entry is alive:
+///
+/// ```compile_fail
+/// # extern crate unqlite;
+/// #
+/// use unqlite::{Cursor, UnQLite};
+///
+/// # fn main() {
+/// let entry = {
+///     let unqlite = UnQLite::create_in_memory();
+///     unqlite.first()
+/// };
+/// # }
+/// ```
+pub struct Entry<'db> {
+    /// Always points to a record
+    cursor: KvCursor<'db>,
+}
 
-impl Entry {
+impl<'db> Entry<'db> {
     /// Returns the key of record
     pub fn key(&self) -> Vec<u8> {
-        self.0.key().unwrap()
+        self.cursor.key().unwrap()
     }
     /// Returns the value
     pub fn value(&self) -> Vec<u8> {
-        self.0.value().unwrap()
+        self.cursor.value().unwrap()
     }
     /// Returns the key-value pair
     pub fn key_value(&self) -> (Vec<u8>, Vec<u8>) {
-        self.0.key_value().unwrap()
+        (self.key(), self.value())
+    }
+    /// Returns the length of the key, without copying it
+    pub fn key_len(&self) -> usize {
+        self.cursor.key_len().unwrap()
+    }
+    /// Returns the length of the value, without copying it
+    pub fn value_len(&self) -> u64 {
+        self.cursor.value_len().unwrap()
     }
 
-    /// Use mangle function for callback of key.
+    /// Consume the key with a closure, without copying it.
     ///
-    /// The callback function should define as this:
+    /// Return `ControlFlow::Break(())` to stop early, which is not reported as an error.
     ///
     /// ```ignore
-    /// #[no_mangle]
-    /// #[allow(private_no_mangle_fns)]         // <-- this should be added to avoid warning
-    /// pub extern fn print_data(ptr: *const c_void, len: u32, _data: *mut c_void) -> i32 {
-    ///     // Do stuff with (ptr, len)
-    ///     println!("Key/Value length is {}", len);
-    ///     0
-    /// }
+    /// let mut len = 0;
+    /// entry.key_callback(|key| {
+    ///     len += key.len();
+    ///     ControlFlow::Continue(())
+    /// })?;
     /// ```
-    pub fn key_callback(
-        &self,
-        func: extern "C" fn(*const c_void, u32, *mut c_void) -> i32,
-        data: *mut c_void,
-    ) {
-        self.0.key_callback(func, data)
+    pub fn key_callback<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, consumer: F) -> Result<()> {
+        self.cursor.key_callback(consumer)
     }
 
-    /// Use mangle function for callback of value
-    pub fn value_callback(
-        &self,
-        func: extern "C" fn(*const c_void, u32, *mut c_void) -> i32,
-        data: *mut c_void,
-    ) {
-        self.0.value_callback(func, data)
+    /// Consume the value with a closure, without copying it.
+    ///
+    /// The value may come in several chunks, see
+    /// [`KV::kv_fetch_callback`](trait.KV.html#tymethod.kv_fetch_callback).
+    pub fn value_callback<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, consumer: F) -> Result<()> {
+        self.cursor.value_callback(consumer)
     }
 
     /// Goto next entry.
     ///
     /// Returns `None` if there's no valid cursors.
-    pub fn next(self) -> Option<Self> {
-        self.0.next().ok().and_then(|raw| raw.valid()).map(Entry)
+    pub fn next(mut self) -> Option<Self> {
+        self.cursor.next_entry();
+        self.cursor.into_entry()
     }
 
     /// Goto previous entry.
     ///
     /// Returns `None` if no valid cursors.
-    pub fn prev(self) -> Option<Self> {
-        self.0.prev().ok().and_then(|raw| raw.valid()).map(Entry)
+    pub fn prev(mut self) -> Option<Self> {
+        self.cursor.prev_entry();
+        self.cursor.into_entry()
     }
 
     /// Delete the pointed record.
-    pub fn delete(self) -> Option<Self> {
-        self.0.delete().ok().and_then(|raw| raw.valid()).map(Entry)
+    pub fn delete(mut self) -> Option<Self> {
+        self.cursor.delete_entry();
+        self.cursor.into_entry()
+    }
+
+    /// Turn the entry into a cursor pointing to the same record, to reposition it at will.
+    pub fn into_cursor(self) -> KvCursor<'db> {
+        self.cursor
     }
+
+    pub(crate) fn first(unqlite: &'db UnQLite, prefix: Vec<u8>) -> Option<Self> {
+        let mut cursor = KvCursor::open(unqlite, prefix).ok()?;
+        cursor.first_entry();
+        cursor.into_entry()
+    }
+
+    pub(crate) fn last(unqlite: &'db UnQLite, prefix: Vec<u8>) -> Option<Self> {
+        let mut cursor = KvCursor::open(unqlite, prefix).ok()?;
+        cursor.last_entry();
+        cursor.into_entry()
+    }
+
+    pub(crate) fn seek(
+        unqlite: &'db UnQLite,
+        prefix: Vec<u8>,
+        key: &[u8],
+        pos: Direction,
+    ) -> Option<Self> {
+        let mut cursor = KvCursor::open(unqlite, prefix).ok()?;
+        cursor.seek(key, pos);
+        cursor.into_entry()
+    }
+}
+
+/// A cursor opened once and repositioned at will, see
+/// [`Cursor::cursor`](trait.Cursor.html#tymethod.cursor).
+///
+/// Unlike an [`Entry`](struct.Entry.html), which is consumed as it moves, the cursor is moved
+/// in place: each positioning method returns whether it points to a record, and the cursor can
+/// be positioned again either way. Reading a record while it points to none fails with
+/// `ErrorKind::NOTFOUND`.
+///
+/// ```
+/// # extern crate unqlite;
+/// #
+/// use unqlite::{Cursor, Direction, UnQLite, KV};
+///
+/// # #[cfg(feature = "enable-threads")]
+/// # fn main() {
+/// let unqlite = UnQLite::create_in_memory();
+/// unqlite.kv_store("a", "1").unwrap();
+/// unqlite.kv_store("b", "2").unwrap();
+///
+/// let mut cursor = unqlite.cursor().unwrap();
+/// for key in &["b", "c", "a"] {
+///     if cursor.seek(key, Direction::Exact) {
+///         println!("{:?}", cursor.value().unwrap());
+///     }
+/// }
+/// # }
+/// # #[cfg(not(feature = "enable-threads"))]
+/// # fn main() { }
+/// ```
+pub struct KvCursor<'db> {
+    raw: RawCursor<'db>,
+    /// Prefix of the keys visited, stripped from the returned keys
+    prefix: Vec<u8>,
+    /// Whether to skip expired records
+    ttl: bool,
+    /// Whether the cursor points to a record with the prefix
+    positioned: bool,
+}
+
+/// Delete the pointed record along with its time-to-live.
+fn delete(raw: &RawCursor<'_>) -> Result<()> {
+    if !raw.unqlite.ttl_enabled() {
+        return raw.delete();
+    }
+    let key = raw.key()?;
+    raw.delete()?;
+    raw.unqlite.clear_ttl(&key)
+}
+
+/// A step that does not move, for exact seeks.
+fn stay(_: &RawCursor<'_>) -> Result<()> {
+    Err(ErrorKind::NOTFOUND.into())
+}
+
+impl<'db> KvCursor<'db> {
+    pub(crate) fn open(unqlite: &'db UnQLite, prefix: Vec<u8>) -> Result<Self> {
+        Ok(KvCursor {
+            raw: RawCursor::init(unqlite)?,
+            prefix,
+            ttl: unqlite.ttl_enabled(),
+            positioned: false,
+        })
+    }
+
+    /// Point to the first record.
+    pub fn first_entry(&mut self) -> bool {
+        self.position(RawCursor::first, RawCursor::next)
+    }
+
+    /// Point to the last record.
+    pub fn last_entry(&mut self) -> bool {
+        self.position(RawCursor::last, RawCursor::prev)
+    }
+
+    /// Point to the record found by `key` and `pos`, as described in
+    /// [`Cursor::seek`](trait.Cursor.html#tymethod.seek).
+    pub fn seek<K: AsRef<[u8]>>(&mut self, key: K, pos: Direction) -> bool {
+        let mut target = self.prefix.clone();
+        target.extend_from_slice(key.as_ref());
+        let step: fn(&RawCursor<'db>) -> Result<()> = match pos {
+            Direction::Exact => stay,
+            Direction::Le => RawCursor::prev,
+            Direction::Ge => RawCursor::next,
+        };
+        self.position(|raw| raw.seek(&target, pos), step)
+    }
+
+    /// Point to the next record.
+    pub fn next_entry(&mut self) -> bool {
+        self.positioned && self.position(RawCursor::next, RawCursor::next)
+    }
+
+    /// Point to the previous record.
+    pub fn prev_entry(&mut self) -> bool {
+        self.positioned && self.position(RawCursor::prev, RawCursor::prev)
+    }
+
+    /// Delete the pointed record and point to the next one.
+    ///
+    /// As for [`KV::kv_delete`](trait.KV.html#tymethod.kv_delete), this takes the write lock of
+    /// the handle and removes the time-to-live of the record.
+    pub fn delete_entry(&mut self) -> bool {
+        let unqlite = self.raw.unqlite;
+        self.positioned && unqlite.with_update_lock(|| self.position(delete, RawCursor::next))
+    }
+
+    /// Reset the cursor, which then points to no record until positioned again.
+    pub fn reset(&mut self) -> Result<()> {
+        self.positioned = false;
+        self.raw.reset()
+    }
+
+    /// Whether the cursor points to a record.
+    pub fn is_valid(&self) -> bool {
+        self.positioned
+    }
+
+    /// The pointed entry, `None` if there is none.
+    pub fn into_entry(self) -> Option<Entry<'db>> {
+        if self.positioned {
+            Some(Entry { cursor: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the key of the pointed record.
+    pub fn key(&self) -> Result<Vec<u8>> {
+        let mut key = self.pointed()?.key()?;
+        key.drain(..self.prefix.len());
+        Ok(key)
+    }
+
+    /// Returns the value of the pointed record.
+    pub fn value(&self) -> Result<Vec<u8>> {
+        self.pointed()?.value()
+    }
+
+    /// Returns the length of the key, without copying it.
+    pub fn key_len(&self) -> Result<usize> {
+        Ok(self.pointed()?.key_len()? as usize - self.prefix.len())
+    }
+
+    /// Returns the length of the value, without copying it.
+    pub fn value_len(&self) -> Result<u64> {
+        Ok(self.pointed()?.value_len()? as u64)
+    }
+
+    /// Consume the key with a closure, see
+    /// [`Entry::key_callback`](struct.Entry.html#method.key_callback).
+    pub fn key_callback<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, mut consumer: F) -> Result<()> {
+        let mut skip = self.prefix.len();
+        self.pointed()?.key_callback(|data| {
+            if skip >= data.len() {
+                skip -= data.len();
+                return ControlFlow::Continue(());
+            }
+            let data = &data[skip..];
+            skip = 0;
+            consumer(data)
+        })
+    }
+
+    /// Consume the value with a closure, see
+    /// [`Entry::value_callback`](struct.Entry.html#method.value_callback).
+    pub fn value_callback<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, consumer: F) -> Result<()> {
+        self.pointed()?.value_callback(consumer)
+    }
+
+    fn pointed(&self) -> Result<&RawCursor<'db>> {
+        if self.positioned {
+            Ok(&self.raw)
+        } else {
+            Err(ErrorKind::NOTFOUND.into())
+        }
+    }
+
+    /// Move the cursor with `to`, then with `step` until it points to a key starting with the
+    /// prefix, skipping expired records and, at the root, reserved keys.
+    fn position<T, S>(&mut self, to: T, step: S) -> bool
+    where
+        T: FnOnce(&RawCursor<'db>) -> Result<()>,
+        S: Fn(&RawCursor<'db>) -> Result<()>,
+    {
+        let raw = &self.raw;
+        let mut moved = to(raw);
+        self.positioned = loop {
+            if moved.is_err() || !raw.is_valid() {
+                break false;
+            }
+            let visible = if self.prefix.is_empty() {
+                !raw.has_prefix(RESERVED)
+            } else {
+                raw.has_prefix(&self.prefix)
+            };
+            if visible && !(self.ttl && raw.is_expired()) {
+                break true;
+            }
+            moved = step(raw);
+        };
+        self.positioned
+    }
+}
+
+/// Iterator over the records, see [`Cursor::iter`](trait.Cursor.html#tymethod.iter) and
+/// [`Cursor::range`](trait.Cursor.html#tymethod.range).
+///
+/// Each end has its own cursor, opened on the first call to `next` or `next_back`. The
+/// iteration stops once both ends meet, or after the first error.
+pub struct Iter<'db> {
+    unqlite: &'db UnQLite,
+    /// Prefix of the keys visited, stripped from the returned keys
+    prefix: Vec<u8>,
+    /// Bounds of the returned keys
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    /// Whether the engine visits keys in order, so that each end can seek its bound
+    ordered: bool,
+    front: End<'db>,
+    back: End<'db>,
 }
 
+/// Position of one end of an `Iter`.
+enum End<'db> {
+    Start,
+    /// The entry last returned, with its key
+    At(Entry<'db>, Vec<u8>),
+    Done,
+}
+
+impl<'db> End<'db> {
+    fn key(&self) -> Option<&[u8]> {
+        match self {
+            End::At(_, key) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+fn to_owned_bound<K: AsRef<[u8]>>(bound: Bound<&K>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_ref().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl<'db> Iter<'db> {
+    pub(crate) fn new(unqlite: &'db UnQLite, prefix: Vec<u8>) -> Self {
+        Iter {
+            unqlite,
+            prefix,
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            ordered: false,
+            front: End::Start,
+            back: End::Start,
+        }
+    }
+
+    pub(crate) fn range<K, R>(unqlite: &'db UnQLite, prefix: Vec<u8>, range: R) -> Self
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        Iter {
+            start: to_owned_bound(range.start_bound()),
+            end: to_owned_bound(range.end_bound()),
+            ordered: unqlite.is_ordered(),
+            ..Iter::new(unqlite, prefix)
+        }
+    }
+
+    fn after_start(&self, key: &[u8]) -> bool {
+        match self.start {
+            Bound::Included(ref start) => key >= &start[..],
+            Bound::Excluded(ref start) => key > &start[..],
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn before_end(&self, key: &[u8]) -> bool {
+        match self.end {
+            Bound::Included(ref end) => key <= &end[..],
+            Bound::Excluded(ref end) => key < &end[..],
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn stop(&mut self) {
+        self.front = End::Done;
+        self.back = End::Done;
+    }
+
+    /// The first entry of the front end, or of the back end if `back`.
+    fn open(&self, back: bool) -> Option<Entry<'db>> {
+        let (unqlite, prefix) = (self.unqlite, self.prefix.clone());
+        match (back, &self.start, &self.end) {
+            (false, Bound::Included(key), _) | (false, Bound::Excluded(key), _) if self.ordered => {
+                Entry::seek(unqlite, prefix, key, Direction::Ge)
+            }
+            (true, _, Bound::Included(key)) | (true, _, Bound::Excluded(key)) if self.ordered => {
+                Entry::seek(unqlite, prefix, key, Direction::Le)
+            }
+            (false, ..) => Entry::first(unqlite, prefix),
+            (true, ..) => Entry::last(unqlite, prefix),
+        }
+    }
+
+    /// Iterate over the keys only, without reading the values.
+    pub fn keys(self) -> Keys<'db> {
+        Keys(self)
+    }
+
+    /// Iterate over the values only.
+    pub fn values(self) -> Values<'db> {
+        Values(self)
+    }
+
+    /// Step the back end backwards if `back`, the front end forwards otherwise, to the next key
+    /// within the bounds, and `read` the record from its entry and key without the prefix.
+    ///
+    /// Both ends stop once one of them is exhausted, goes past its bound on an ordered engine,
+    /// or reaches the record last returned by the other end.
+    fn step<T, F>(&mut self, back: bool, read: F) -> Option<Result<T>>
+    where
+        F: FnOnce(&Entry<'db>, &[u8]) -> Result<T>,
+    {
+        let state = if back { &mut self.back } else { &mut self.front };
+        let mut entry = match mem::replace(state, End::Done) {
+            End::Start => self.open(back),
+            End::At(entry, _) if back => entry.prev(),
+            End::At(entry, _) => entry.next(),
+            End::Done => return None,
+        };
+        let record = loop {
+            let current = match entry {
+                Some(current) => current,
+                None => break None,
+            };
+            let key = match current.cursor.raw.key() {
+                Ok(key) => key,
+                Err(err) => break Some(Err(err)),
+            };
+            let other = if back { &self.front } else { &self.back };
+            if other.key() == Some(&key[..]) {
+                break None;
+            }
+            let stripped = &key[self.prefix.len()..];
+            if self.after_start(stripped) && self.before_end(stripped) {
+                break Some(read(&current, stripped).map(|item| (current, key, item)));
+            }
+            let past = if back {
+                !self.after_start(stripped)
+            } else {
+                !self.before_end(stripped)
+            };
+            if self.ordered && past {
+                break None;
+            }
+            entry = if back { current.prev() } else { current.next() };
+        };
+        match record {
+            Some(Ok((entry, key, item))) => {
+                let state = if back { &mut self.back } else { &mut self.front };
+                *state = End::At(entry, key);
+                Some(Ok(item))
+            }
+            Some(Err(err)) => {
+                self.stop();
+                Some(Err(err))
+            }
+            None => {
+                self.stop();
+                None
+            }
+        }
+    }
+}
+
+fn read_key_value(entry: &Entry<'_>, key: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    Ok((key.to_vec(), entry.cursor.raw.value()?))
+}
+
+fn read_key(_: &Entry<'_>, key: &[u8]) -> Result<Vec<u8>> {
+    Ok(key.to_vec())
+}
+
+fn read_value(entry: &Entry<'_>, _: &[u8]) -> Result<Vec<u8>> {
+    entry.cursor.raw.value()
+}
+
+impl<'db> Iterator for Iter<'db> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(false, read_key_value)
+    }
+}
+
+impl<'db> DoubleEndedIterator for Iter<'db> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(true, read_key_value)
+    }
+}
+
+impl<'db> FusedIterator for Iter<'db> {}
+
+/// Iterator over the keys, see [`Cursor::keys`](trait.Cursor.html#method.keys).
+pub struct Keys<'db>(Iter<'db>);
+
+impl<'db> Iterator for Keys<'db> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.step(false, read_key)
+    }
+}
+
+impl<'db> DoubleEndedIterator for Keys<'db> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.step(true, read_key)
+    }
+}
+
+impl<'db> FusedIterator for Keys<'db> {}
+
+/// Iterator over the values, see [`Cursor::values`](trait.Cursor.html#method.values).
+pub struct Values<'db>(Iter<'db>);
+
+impl<'db> Iterator for Values<'db> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.step(false, read_value)
+    }
+}
+
+impl<'db> DoubleEndedIterator for Values<'db> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.step(true, read_value)
+    }
+}
+
+impl<'db> FusedIterator for Values<'db> {}
+
+/// Seek direction, see [`Cursor::seek`](trait.Cursor.html#tymethod.seek).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Direction {
     /// Seek the cursor exactly
     Exact = UNQLITE_CURSOR_MATCH_EXACT as isize,
@@ -179,51 +787,54 @@ pub enum Direction {
     Ge = UNQLITE_CURSOR_MATCH_GE as isize,
 }
 
-struct RawCursor {
-    engine: NonNull<unqlite>,
+impl Direction {
+    pub(crate) fn from_raw(pos: i32) -> Direction {
+        match pos as u32 {
+            UNQLITE_CURSOR_MATCH_LE => Direction::Le,
+            UNQLITE_CURSOR_MATCH_GE => Direction::Ge,
+            _ => Direction::Exact,
+        }
+    }
+}
+
+struct RawCursor<'db> {
+    unqlite: &'db UnQLite,
     cursor: NonNull<unqlite_kv_cursor>,
 }
 
+/// Call `unqlite_kv_cursor_$i` on the cursor of `$self_`, honouring the busy policy of its handle.
 macro_rules! eval {
-    ($i: ident, $($e: expr),*) => (
-        unsafe {
+    ($self_: ident, $i: ident $(, $e: expr)*) => (
+        $self_.unqlite.busy().eval(|| unsafe {
             paste::expr! {
-                [<unqlite_kv_cursor_ $i>]($($e),*)
+                [<unqlite_kv_cursor_ $i>]($self_.cursor() $(, $e)*)
             }
-        }
+        })
     );
 }
 
 macro_rules! wrap {
-    ($i: ident, $($e: expr),*) => (eval!($i, $($e),*).wrap());
+    ($self_: ident, $i: ident $(, $e: expr)*) => (eval!($self_, $i $(, $e)*).wrap());
 }
 
-macro_rules! wrap_in_place {
-    ($self_:ident, $i: ident) => (
-        wrap!($i, $self_.cursor()).map(|_| $self_)
-    );
-    ($self_:ident, $i: ident, $($e: expr),+) => (
-        wrap!($i, $self_.cursor(), $($e),+).map(|_| $self_)
-    );
-}
-
-impl RawCursor {
+impl<'db> RawCursor<'db> {
     /// Opening Database Cursors
-    pub fn init(unqlite: &UnQLite) -> Result<Self> {
-        let mut cursor: *mut unqlite_kv_cursor = unsafe { mem::MaybeUninit::uninit().assume_init() };
-        wrap!(init, unqlite.as_raw_mut_ptr(), &mut cursor).map(|_| RawCursor {
-            engine: unqlite.engine,
-            cursor: unsafe { NonNull::new_unchecked(cursor) },
-        })
+    pub fn init(unqlite: &'db UnQLite) -> Result<Self> {
+        let mut cursor: *mut unqlite_kv_cursor = ptr::null_mut();
+        eval_with!(unqlite.busy(), kv_cursor_init, unqlite.as_raw_mut_ptr(), &mut cursor)
+            .wrap()
+            .map(|_| RawCursor {
+                unqlite,
+                cursor: unsafe { NonNull::new_unchecked(cursor) },
+            })
     }
 
-    #[allow(dead_code)]
-    pub fn reset(self) -> Result<Self> {
-        wrap_in_place!(self, reset)
+    pub fn reset(&self) -> Result<()> {
+        wrap!(self, reset)
     }
 
     pub fn release(&self) -> Result<()> {
-        wrap!(release, self.engine(), self.cursor())
+        eval_with!(self.unqlite.busy(), kv_cursor_release, self.engine(), self.cursor()).wrap()
     }
 
     /// # Positioning Database Cursors
@@ -234,8 +845,8 @@ impl RawCursor {
     /// * next
     /// * prev
     ///
-    pub fn seek<Key: AsRef<[u8]>>(self, key: Key, pos: Direction) -> Result<Self> {
-        wrap_in_place!(
+    pub fn seek<Key: AsRef<[u8]>>(&self, key: Key, pos: Direction) -> Result<()> {
+        wrap!(
             self,
             seek,
             key.as_ref().as_ptr() as _,
@@ -243,101 +854,125 @@ impl RawCursor {
             pos as _
         )
     }
-    pub fn first(self) -> Result<Self> {
-        wrap_in_place!(self, first_entry)
+    pub fn first(&self) -> Result<()> {
+        wrap!(self, first_entry)
     }
-    pub fn last(self) -> Result<Self> {
-        wrap_in_place!(self, last_entry)
+    pub fn last(&self) -> Result<()> {
+        wrap!(self, last_entry)
     }
-    pub fn next(self) -> Result<Self> {
-        wrap_in_place!(self, next_entry)
+    pub fn next(&self) -> Result<()> {
+        wrap!(self, next_entry)
     }
-    pub fn prev(self) -> Result<Self> {
-        wrap_in_place!(self, prev_entry)
+    pub fn prev(&self) -> Result<()> {
+        wrap!(self, prev_entry)
     }
 
     /// Check if the cursor reperesent a valid entry
     pub fn is_valid(&self) -> bool {
-        match eval!(valid_entry, self.cursor()) {
+        match unsafe { unqlite_kv_cursor_valid_entry(self.cursor()) } {
             1 => true,
             0 => false,
             _ => unreachable!(),
         }
     }
 
-    pub fn valid(self) -> Option<Self> {
-        if self.is_valid() {
-            Some(self)
-        } else {
-            None
-        }
-    }
-
     /// Extracting Data from Database Cursors
     pub fn key(&self) -> Result<Vec<u8>> {
         debug_assert!(self.is_valid());
 
         self.key_len().and_then(|mut len| {
-            let ptr = unsafe { ::libc::malloc(len as _) };
-            wrap!(key, self.cursor(), ptr as _, &mut len)
-                .map(|_| unsafe { Vec::from_raw_parts(ptr as _, len as _, len as _) })
+            let mut buf = vec![0u8; len as usize];
+            wrap!(self, key, buf.as_mut_ptr() as _, &mut len).map(|_| {
+                buf.truncate(len as usize);
+                buf
+            })
         })
     }
 
-    pub fn key_callback(
-        &self,
-        func: extern "C" fn(*const c_void, u32, *mut c_void) -> i32,
-        data: *mut c_void,
-    ) {
-        eval!(key_callback, self.cursor(), Some(func), data);
+    pub fn key_callback<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, consumer: F) -> Result<()> {
+        with_consumer(consumer, |raw, data| {
+            eval!(self, key_callback, Some(raw), data)
+        })
     }
 
     pub fn value(&self) -> Result<Vec<u8>> {
         debug_assert!(self.is_valid());
 
         self.value_len().and_then(|mut len| {
-            let ptr = unsafe { ::libc::malloc(len as _) };
-            wrap!(data, self.cursor(), ptr as _, &mut len)
-                .map(|_| unsafe { Vec::from_raw_parts(ptr as _, len as _, len as _) })
+            let mut buf = vec![0u8; len as usize];
+            wrap!(self, data, buf.as_mut_ptr() as _, &mut len).map(|_| {
+                buf.truncate(len as usize);
+                buf
+            })
         })
     }
 
-    pub fn value_callback(
-        &self,
-        func: extern "C" fn(*const c_void, u32, *mut c_void) -> i32,
-        data: *mut c_void,
-    ) {
-        eval!(data_callback, self.cursor(), Some(func), data);
+    pub fn value_callback<F: FnMut(&[u8]) -> ControlFlow<()>>(&self, consumer: F) -> Result<()> {
+        with_consumer(consumer, |raw, data| {
+            eval!(self, data_callback, Some(raw), data)
+        })
     }
 
-    pub fn key_value(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+    /// Check if the engine of the cursor visits keys in order.
+    pub fn is_ordered(&self) -> bool {
+        unsafe { engine::is_ordered((*self.cursor()).pStore) }
+    }
+
+    /// Check if transactions apply to the engine of the cursor.
+    pub fn is_transactional(&self) -> bool {
+        unsafe { !engine::is_registered((*self.cursor()).pStore) }
+    }
+
+    /// Check if the record has expired, see the `ttl` module.
+    pub fn is_expired(&self) -> bool {
         self.key()
-            .and_then(|key| self.value().map(|value| (key, value)))
+            .is_ok_and(|key| ttl::is_expired(self.unqlite, &key))
+    }
+
+    /// Check if the key starts with `prefix`, without copying it.
+    pub fn has_prefix(&self, prefix: &[u8]) -> bool {
+        let mut rest = prefix;
+        self.key_len().is_ok_and(|len| len as usize >= prefix.len())
+            && self
+                .key_callback(|data| {
+                    let n = rest.len().min(data.len());
+                    if data[..n] != rest[..n] {
+                        return ControlFlow::Break(());
+                    }
+                    rest = &rest[n..];
+                    if rest.is_empty() {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                })
+                .is_ok()
+            && rest.is_empty()
     }
 
     /// Deleting Records using Database Cursors
-    pub fn delete(self) -> Result<Self> {
-        wrap_in_place!(self, delete_entry)
+    pub fn delete(&self) -> Result<()> {
+        wrap!(self, delete_entry)
     }
 
     pub fn key_len(&self) -> Result<i32> {
         let mut len = 0i32;
-        wrap!(key, self.cursor(), ptr::null_mut() as _, &mut len).map(|_| len)
+        wrap!(self, key, ptr::null_mut() as _, &mut len).map(|_| len)
     }
     pub fn value_len(&self) -> Result<i64> {
         let mut len = 0i64;
-        wrap!(data, self.cursor(), ptr::null_mut() as _, &mut len).map(|_| len)
+        wrap!(self, data, ptr::null_mut() as _, &mut len).map(|_| len)
     }
 
     unsafe fn cursor(&self) -> *mut unqlite_kv_cursor {
         self.cursor.as_ptr()
     }
     unsafe fn engine(&self) -> *mut unqlite {
-        self.engine.as_ptr()
+        self.unqlite.as_raw_mut_ptr()
     }
 }
 
-impl Drop for RawCursor {
+impl<'db> Drop for RawCursor<'db> {
     fn drop(&mut self) {
         let _ = self.release();
     }
@@ -347,8 +982,6 @@ impl Drop for RawCursor {
 #[cfg(feature = "enable-threads")]
 mod tests {
     use super::*;
-    use std::os::raw::c_void;
-    use std::ptr;
     use crate::{UnQLite, KV};
 
     macro_rules! _test_assert_eq {
@@ -367,12 +1000,6 @@ mod tests {
         };
     }
 
-    #[no_mangle]
-    pub extern "C" fn print_data(ptr: *const c_void, _len: u32, _data: *mut c_void) -> i32 {
-        println!("Key callback: {:?}", ptr);
-        0
-    }
-
     #[test]
     fn test_kv_cursor() {
         let unqlite = UnQLite::create_in_memory();
@@ -389,7 +1016,22 @@ mod tests {
         _test_assert_eq!(entry.value(), "3");
         _test_assert_eq!(entry.key_value(), ("cde", "3"));
         let entry = entry.next().unwrap();
-        entry.key_callback(print_data, ptr::null_mut());
+        let mut key = Vec::new();
+        entry
+            .key_callback(|data| {
+                key.extend_from_slice(data);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(key, b"bcd");
+        let mut value = Vec::new();
+        entry
+            .value_callback(|data| {
+                value.extend_from_slice(data);
+                ControlFlow::Break(())
+            })
+            .unwrap();
+        assert_eq!(value, b"2");
         _test_assert_eq!(entry.key(), "bcd");
         _test_assert_eq!(entry.value(), "2");
         _test_assert_eq!(entry.key_value(), ("bcd", "2"));
@@ -408,6 +1050,159 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_iter() {
+        let unqlite = UnQLite::create_in_memory();
+        assert!(unqlite.iter().next().is_none());
+        for i in 0..5 {
+            unqlite.kv_store(format!("key{}", i), i.to_string()).unwrap();
+        }
+
+        let records: Vec<_> = unqlite.iter().map(|record| record.unwrap()).collect();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0], unqlite.first().unwrap().key_value());
+        let mut reversed: Vec<_> = unqlite.iter().rev().map(|record| record.unwrap()).collect();
+        reversed.reverse();
+        assert_eq!(reversed, records);
+
+        // Both ends meet without returning a record twice.
+        for len in 4..=5 {
+            unqlite.kv_delete("key4").unwrap();
+            if len == 5 {
+                unqlite.kv_store("key4", "4").unwrap();
+            }
+            let mut iter = unqlite.iter();
+            let mut seen = Vec::new();
+            while let Some(record) = iter.next() {
+                seen.push(record.unwrap());
+                if let Some(record) = iter.next_back() {
+                    seen.push(record.unwrap());
+                }
+            }
+            assert!(iter.next_back().is_none());
+            seen.sort();
+            seen.dedup();
+            assert_eq!(seen.len(), len);
+        }
+
+        let keys: Vec<_> = unqlite
+            .iter()
+            .filter_map(|record| record.ok())
+            .filter(|(_, value)| value != b"0")
+            .take(2)
+            .collect();
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn test_range() {
+        let unqlite = UnQLite::create_in_memory();
+        assert!(!unqlite.is_ordered());
+        for key in &["d", "a", "c", "b", "e"] {
+            unqlite.kv_store(key, key.to_uppercase()).unwrap();
+        }
+
+        fn keys<I: Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>>(range: I) -> Vec<Vec<u8>> {
+            let mut keys: Vec<_> = range.map(|record| record.unwrap().0).collect();
+            keys.sort();
+            keys
+        }
+        assert_eq!(keys(unqlite.range("b".."d")), [b"b", b"c"]);
+        assert_eq!(keys(unqlite.range("b"..="d").rev()), [b"b", b"c", b"d"]);
+        assert_eq!(keys(unqlite.range("c"..)), [b"c", b"d", b"e"]);
+        assert!(keys(unqlite.range("x"..)).is_empty());
+
+        let mut range = unqlite.range("b".."e");
+        let mut seen = Vec::new();
+        while let Some(record) = range.next_back() {
+            seen.push(record.unwrap());
+            if let Some(record) = range.next() {
+                seen.push(record.unwrap());
+            }
+        }
+        assert_eq!(seen.len(), 3);
+        assert!(range.next().is_none());
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        assert_eq!(
+            prefix_range(b"a\xff\xff"),
+            (Bound::Included(b"a\xff\xff".to_vec()), Bound::Excluded(b"b".to_vec()))
+        );
+        assert_eq!(prefix_range(b"\xff").1, Bound::Unbounded);
+
+        let unqlite = UnQLite::create_in_memory();
+        for key in &["user:4", "user:42:a", "user:42", "user:42:b", "user:43:a"] {
+            unqlite.kv_store(key, "value").unwrap();
+        }
+        let mut keys: Vec<_> = unqlite
+            .scan_prefix("user:42:")
+            .map(|record| record.unwrap().0)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, [b"user:42:a", b"user:42:b"]);
+        assert_eq!(unqlite.scan_prefix("").count(), 5);
+        assert_eq!(unqlite.scan_prefix("user:42").rev().count(), 3);
+    }
+
+    #[test]
+    fn test_keys_values() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("abc", "1").unwrap();
+        unqlite.kv_store("de", "2345").unwrap();
+
+        let entry = unqlite.seek("de", Direction::Exact).unwrap();
+        assert_eq!((entry.key_len(), entry.value_len()), (2, 4));
+
+        let records: Vec<_> = unqlite.iter().map(|record| record.unwrap()).collect();
+        let keys: Vec<_> = unqlite.keys().map(|key| key.unwrap()).collect();
+        let values: Vec<_> = unqlite.values().rev().map(|value| value.unwrap()).collect();
+        assert_eq!(keys, [records[0].0.clone(), records[1].0.clone()]);
+        assert_eq!(values, [records[1].1.clone(), records[0].1.clone()]);
+
+        let keys: Vec<_> = unqlite.scan_prefix("d").keys().collect::<Result<_>>().unwrap();
+        assert_eq!(keys, [b"de"]);
+    }
+
+    #[test]
+    fn test_reusable_cursor() {
+        let unqlite = UnQLite::create_in_memory();
+        for key in &["a", "b", "c"] {
+            unqlite.kv_store(key, key.to_uppercase()).unwrap();
+        }
+
+        let mut cursor = unqlite.cursor().unwrap();
+        assert!(!cursor.is_valid());
+        assert_eq!(cursor.key().unwrap_err().kind(), Some(ErrorKind::NOTFOUND));
+        for key in &["c", "a", "b"] {
+            assert!(cursor.seek(key, Direction::Exact));
+            assert_eq!(cursor.key().unwrap(), key.as_bytes());
+            assert_eq!(cursor.value().unwrap(), key.to_uppercase().as_bytes());
+        }
+        assert!(!cursor.seek("x", Direction::Exact));
+        assert!(!cursor.next_entry());
+        assert!(cursor.first_entry());
+        cursor.reset().unwrap();
+        assert!(!cursor.is_valid());
+
+        let mut count = 0;
+        let mut more = cursor.last_entry();
+        while more {
+            count += 1;
+            more = cursor.prev_entry();
+        }
+        assert_eq!(count, 3);
+
+        let entry = unqlite.seek("b", Direction::Exact).unwrap();
+        let mut cursor = entry.into_cursor();
+        assert_eq!(cursor.value_len().unwrap(), 1);
+        cursor.delete_entry();
+        assert!(!unqlite.kv_contains("b"));
+        assert!(cursor.seek("a", Direction::Exact));
+        assert_eq!(cursor.into_entry().unwrap().key(), b"a");
+    }
+
     #[test]
     fn test_delete() {
         let uq = UnQLite::create_temp();
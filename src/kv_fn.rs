@@ -0,0 +1,174 @@
+//! Rust key comparison and hash functions for the Key/Value engine.
+//!
+//! The engine calls these functions without any user data, so every installed closure takes one
+//! of a fixed number of slots, each slot having its own `extern "C"` trampoline. A slot is held by
+//! its `UnQLite` handle and released when the handle is closed.
+
+use crate::error::{ErrorKind, Result};
+use std::cmp::Ordering;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::slice;
+use std::sync::RwLock;
+
+/// Signature of the comparison functions of the C API.
+pub(crate) type RawCmp = extern "C" fn(*const c_void, *const c_void, u32) -> i32;
+
+/// Signature of the hash functions of the C API.
+pub(crate) type RawHash = extern "C" fn(*const c_void, u32) -> u32;
+
+type CmpFn = dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync;
+type HashFn = dyn Fn(&[u8]) -> u32 + Send + Sync;
+
+/// Maximum number of comparison functions, and of hash functions, installed at the same time.
+const SLOTS: usize = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CMP: RwLock<Option<Box<CmpFn>>> = RwLock::new(None);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HASH: RwLock<Option<Box<HashFn>>> = RwLock::new(None);
+
+static CMP_SLOTS: [RwLock<Option<Box<CmpFn>>>; SLOTS] = [NO_CMP; SLOTS];
+static HASH_SLOTS: [RwLock<Option<Box<HashFn>>>; SLOTS] = [NO_HASH; SLOTS];
+
+macro_rules! trampolines {
+    ($f: ident; $($i: expr)*) => ([$($f::<$i>),*]);
+}
+
+static CMP_TRAMPOLINES: [RawCmp; SLOTS] = trampolines!(cmp_trampoline;
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
+static HASH_TRAMPOLINES: [RawHash; SLOTS] = trampolines!(hash_trampoline;
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
+
+/// Functions installed on a handle, kept alive as long as the engine may call them.
+#[derive(Default)]
+pub(crate) struct KvFns {
+    pub(crate) cmp: Option<CmpSlot>,
+    pub(crate) hash: Option<HashSlot>,
+}
+
+/// A claimed comparison slot, released on drop.
+pub(crate) struct CmpSlot(usize);
+
+/// A claimed hash slot, released on drop.
+pub(crate) struct HashSlot(usize);
+
+impl CmpSlot {
+    /// Claim a free slot for `f`, `ErrorKind::LIMIT` if there is none.
+    pub(crate) fn claim(f: Box<CmpFn>) -> Result<Self> {
+        claim(&CMP_SLOTS, f).map(CmpSlot)
+    }
+
+    /// The C function calling the closure of this slot.
+    pub(crate) fn raw(&self) -> RawCmp {
+        CMP_TRAMPOLINES[self.0]
+    }
+}
+
+impl HashSlot {
+    /// Claim a free slot for `f`, `ErrorKind::LIMIT` if there is none.
+    pub(crate) fn claim(f: Box<HashFn>) -> Result<Self> {
+        claim(&HASH_SLOTS, f).map(HashSlot)
+    }
+
+    /// The C function calling the closure of this slot.
+    pub(crate) fn raw(&self) -> RawHash {
+        HASH_TRAMPOLINES[self.0]
+    }
+}
+
+impl Drop for CmpSlot {
+    fn drop(&mut self) {
+        *CMP_SLOTS[self.0].write().unwrap() = None;
+    }
+}
+
+impl Drop for HashSlot {
+    fn drop(&mut self) {
+        *HASH_SLOTS[self.0].write().unwrap() = None;
+    }
+}
+
+fn claim<T: ?Sized>(slots: &[RwLock<Option<Box<T>>>], f: Box<T>) -> Result<usize> {
+    for (index, slot) in slots.iter().enumerate() {
+        let mut slot = slot.write().unwrap();
+        if slot.is_none() {
+            *slot = Some(f);
+            return Ok(index);
+        }
+    }
+    Err(ErrorKind::LIMIT.into())
+}
+
+unsafe fn bytes<'a>(data: *const c_void, len: u32) -> &'a [u8] {
+    if len == 0 || data.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(data as *const u8, len as usize)
+    }
+}
+
+/// Run `f`, aborting on panic since there is no way to report it to the engine.
+fn no_unwind<T, F: FnOnce() -> T>(f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| process::abort())
+}
+
+extern "C" fn cmp_trampoline<const I: usize>(
+    lhs: *const c_void,
+    rhs: *const c_void,
+    len: u32,
+) -> i32 {
+    no_unwind(|| {
+        let slot = CMP_SLOTS[I].read().unwrap();
+        let cmp = slot.as_ref().expect("comparison slot in use");
+        cmp(unsafe { bytes(lhs, len) }, unsafe { bytes(rhs, len) }) as i32
+    })
+}
+
+extern "C" fn hash_trampoline<const I: usize>(key: *const c_void, len: u32) -> u32 {
+    no_unwind(|| {
+        let slot = HASH_SLOTS[I].read().unwrap();
+        let hash = slot.as_ref().expect("hash slot in use");
+        hash(unsafe { bytes(key, len) })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{claim, CmpSlot, HashFn, HashSlot, SLOTS};
+    use crate::ErrorKind;
+    use std::ffi::c_void;
+    use std::sync::RwLock;
+
+    #[test]
+    fn slots() {
+        let hash = HashSlot::claim(Box::new(|key: &[u8]| key.len() as u32)).unwrap();
+        assert_eq!((hash.raw())(b"abc".as_ptr() as *const c_void, 3), 3);
+
+        let cmp = CmpSlot::claim(Box::new(|lhs: &[u8], rhs: &[u8]| lhs.cmp(rhs))).unwrap();
+        let (a, b) = (
+            b"a".as_ptr() as *const c_void,
+            b"b".as_ptr() as *const c_void,
+        );
+        assert_eq!((cmp.raw())(a, b, 1), -1);
+        assert_eq!((cmp.raw())(b, a, 1), 1);
+        assert_eq!((cmp.raw())(a, a, 1), 0);
+    }
+
+    #[test]
+    fn limit() {
+        // A table of its own, the global ones are shared with the tests running alongside.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const FREE: RwLock<Option<Box<HashFn>>> = RwLock::new(None);
+        let slots = [FREE; SLOTS];
+        for index in 0..SLOTS {
+            assert_eq!(claim(&slots, Box::new(|_: &[u8]| 0)).unwrap(), index);
+        }
+        let err = claim(&slots, Box::new(|_: &[u8]| 0)).unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::LIMIT));
+
+        *slots[3].write().unwrap() = None;
+        assert_eq!(claim(&slots, Box::new(|_: &[u8]| 0)).unwrap(), 3);
+    }
+}
@@ -1,5 +1,9 @@
-use crate::error::{Result, Wrap};
+use crate::callback::with_consumer;
+use crate::error::{Error, ErrorKind, Result, Wrap};
+use crate::kv_fn::{CmpSlot, HashSlot};
 use crate::ffi::{
+    unqlite_begin,
+    unqlite_commit,
     unqlite_kv_append,
     unqlite_kv_config,
     unqlite_kv_delete,
@@ -9,11 +13,19 @@ use crate::ffi::{
     unqlite_kv_fetch_callback,
     unqlite_kv_store,
 };
-use std::mem;
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::error;
+use std::fmt;
+use std::ops::{ControlFlow, Deref, RangeBounds};
 use std::os::raw::c_void;
 use std::ptr;
-use crate::vars::{UNQLITE_KV_CONFIG_CMP_FUNC, UNQLITE_KV_CONFIG_HASH_FUNC};
-use crate::UnQLite;
+use std::sync::PoisonError;
+use std::time::Duration;
+use crate::vars::{UNQLITE_KV_CONFIG_CMP_FUNC, UNQLITE_KV_CONFIG_HASH_FUNC, UNQLITE_OK};
+use crate::stream::{KvReader, KvWriter};
+use crate::kv_cursor::prefix_range;
+use crate::{Iter, Transaction, UnQLite};
 
 /// Key-Value Store Interface
 pub trait KV {
@@ -38,6 +50,40 @@ pub trait KV {
     /// unqlite_kv_cursor_delete_entry().
     fn kv_delete<K: AsRef<[u8]>>(&self, key: K) -> Result<()>;
 
+    /// Remove every record whose key is within `range`, returns how many were removed.
+    ///
+    /// Keys are compared as byte strings, as by
+    /// [`Cursor::range`](trait.Cursor.html#tymethod.range): only an ordered engine seeks to the
+    /// start of the range, others visit every record. Records expiring meanwhile are not
+    /// counted. The records are removed within a single transaction, as described for
+    /// [`kv_compare_and_swap`](#tymethod.kv_compare_and_swap). Fails with
+    /// `ErrorKind::NOTIMPLEMENTED` if the engine is not transactional. On a database, records of
+    /// [buckets](struct.Bucket.html) are left alone.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// for key in &["a", "b", "c", "d"] {
+    ///     unqlite.kv_store(key, "value").unwrap();
+    /// }
+    /// assert_eq!(unqlite.kv_delete_range("b".."d").unwrap(), 2);
+    /// assert!(unqlite.kv_contains("a") && unqlite.kv_contains("d"));
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_delete_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<usize>;
+
+    /// Remove every record whose key starts with `prefix`, returns how many were removed.
+    ///
+    /// See [`kv_delete_range`](#tymethod.kv_delete_range).
+    fn kv_delete_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<usize>;
+
     /// Check if `key` is contained in database.
     fn kv_contains<K: AsRef<[u8]>>(&self, key: K) -> bool;
 
@@ -46,18 +92,295 @@ pub trait KV {
 
     /// Fetch a record from the database.
     ///
-    /// Fetch a record from the database and copy its content to a `Vec<u8>`.
+    /// Fetch a record from the database and copy its content to a `Vec<u8>`. The record is read
+    /// in a single engine call, so it is complete even if another thread updates it meanwhile.
     ///
     /// The recommended interface for extracting very large data from the database is
     /// kv_fetch_callback() where the user simply need to supply a consumer callback
     /// instead of a buffer which may be unacceptable when dealing with very large records.
     fn kv_fetch<K: AsRef<[u8]>>(&self, key: K) -> Result<Vec<u8>>;
 
+    /// Fetch a record into `buf`, reusing its allocation.
+    ///
+    /// `buf` is cleared first. Like `kv_fetch`, the record is read in a single engine call, so
+    /// it is complete even if another thread updates it meanwhile.
+    ///
+    /// ```ignore
+    /// let mut buf = Vec::new();
+    /// for key in keys {
+    ///     unqlite.kv_fetch_into(key, &mut buf)?;
+    ///     process(&buf);
+    /// }
+    /// ```
+    fn kv_fetch_into<K: AsRef<[u8]>>(&self, key: K, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Fetch several records at once.
+    ///
+    /// Each key gets its own result: `Ok(None)` if there is no such record, or the error which
+    /// prevented reading it. The keys are read in a single pass through one buffer, and the busy
+    /// policy of the handle applies to the pass as a whole: a key finding the database locked is
+    /// retried where the pass stopped, and once the policy gives up, that key and the ones after
+    /// it fail with `ErrorKind::LOCKED` or `ErrorKind::BUSY`.
+    ///
+    /// Each value is read in a single engine call, but the keys are not read as a snapshot:
+    /// writes from other threads may happen in between. No lock of the handle is taken, so this
+    /// may be called from [`kv_update`](#tymethod.kv_update).
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// unqlite.kv_store("a", "1").unwrap();
+    ///
+    /// let values = unqlite.kv_fetch_many(&["a", "b"]);
+    /// assert_eq!(values[0].as_ref().unwrap(), &Some(b"1".to_vec()));
+    /// assert_eq!(values[1].as_ref().unwrap(), &None);
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_fetch_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<Vec<u8>>>>;
+
+    /// Check several keys at once, in a single pass as for
+    /// [`kv_fetch_many`](#tymethod.kv_fetch_many) but without reading the values.
+    ///
+    /// Unlike [`kv_contains`](#tymethod.kv_contains), errors other than a missing record are
+    /// reported.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// unqlite.kv_store("a", "1").unwrap();
+    ///
+    /// let found = unqlite.kv_contains_many(&["a", "b"]);
+    /// assert!(found[0].as_ref().unwrap());
+    /// assert!(!found[1].as_ref().unwrap());
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_contains_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<bool>>;
+
     /// Fetch a record from the database and invoke the supplied callback to consume its data.
-    fn kv_fetch_callback<K: AsRef<[u8]>>(
+    ///
+    /// The engine hands the data over in one or more chunks, so large records can be streamed
+    /// into any buffer without an intermediate copy. Return `ControlFlow::Break(())` to stop
+    /// early, which is not reported as an error. A panic in `consumer` is resumed once the engine
+    /// call returns.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use std::ops::ControlFlow;
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// unqlite.kv_store("key", "value").unwrap();
+    ///
+    /// let mut buf = Vec::new();
+    /// unqlite
+    ///     .kv_fetch_callback("key", |data| {
+    ///         buf.extend_from_slice(data);
+    ///         ControlFlow::Continue(())
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(buf, b"value");
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_fetch_callback<K, F>(&self, key: K, consumer: F) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8]) -> ControlFlow<()>;
+
+    /// Open a record for reading as a `std::io::Read` stream.
+    ///
+    /// The value is fetched in chunks, so large records can be piped to files or sockets without
+    /// holding them fully in memory. Fails with `ErrorKind::NOTFOUND` if there is no such record.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use std::io::{self, Write};
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// let mut writer = unqlite.kv_writer("blob");
+    /// writer.write_all(b"large value").unwrap();
+    /// writer.finish().unwrap();
+    ///
+    /// let mut reader = unqlite.kv_reader("blob").unwrap();
+    /// let mut value = Vec::new();
+    /// io::copy(&mut reader, &mut value).unwrap();
+    /// assert_eq!(value, b"large value");
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_reader<K: AsRef<[u8]>>(&self, key: K) -> Result<KvReader<'_>>;
+
+    /// Open a record for writing as a `std::io::Write` stream.
+    ///
+    /// The written data is staged aside and replaces the record at once in
+    /// [`KvWriter::finish`](struct.KvWriter.html#method.finish). Dropping the writer before that
+    /// discards it and leaves the record untouched.
+    fn kv_writer<K: AsRef<[u8]>>(&self, key: K) -> KvWriter<'_>;
+
+    /// Store a record which expires after `ttl`.
+    ///
+    /// Once expired, the record is missing for every read and removed lazily, see the
+    /// [`ttl`](ttl/index.html) module.
+    fn kv_store_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(
         &self,
         key: K,
-        consumer: extern "C" fn(data: *const c_void, len: u32, user_data: *mut c_void) -> i32,
+        value: V,
+        ttl: Duration,
+    ) -> Result<()>;
+
+    /// Remaining time-to-live of a record, `None` if it does not expire.
+    ///
+    /// Fails with `ErrorKind::NOTFOUND` if there is no such record.
+    fn kv_ttl<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Duration>>;
+
+    /// Make a record persistent, removing its time-to-live.
+    ///
+    /// Fails with `ErrorKind::NOTFOUND` if there is no such record.
+    fn kv_persist<K: AsRef<[u8]>>(&self, key: K) -> Result<()>;
+
+    /// Store a new record, failing with `ErrorKind::EXISTS` if there is already one under `key`.
+    ///
+    /// The check and the write are atomic with respect to the other writes on the same handle,
+    /// see [`kv_compare_and_swap`](#tymethod.kv_compare_and_swap).
+    fn kv_insert_new<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()>;
+
+    /// Overwrite a record, failing with `ErrorKind::NOTFOUND` if there is none under `key`.
+    ///
+    /// Atomic like [`kv_insert_new`](#tymethod.kv_insert_new).
+    fn kv_replace<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()>;
+
+    /// Add `delta` to the counter under `key` and return the new value.
+    ///
+    /// A counter is stored as a 64-bit little-endian integer, and a missing record counts as
+    /// zero. The update is atomic like [`kv_update`](#tymethod.kv_update) and keeps the
+    /// time-to-live of the record. If the record is not 8 bytes long, or the result overflows,
+    /// this fails with a [`CounterError`](enum.CounterError.html) within `Error::Other`.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// assert_eq!(unqlite.kv_incr("hits", 1).unwrap(), 1);
+    /// assert_eq!(unqlite.kv_incr("hits", 10).unwrap(), 11);
+    /// assert_eq!(unqlite.kv_decr("hits", 2).unwrap(), 9);
+    /// assert_eq!(unqlite.kv_fetch("hits").unwrap(), 9i64.to_le_bytes());
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64>;
+
+    /// Subtract `delta` from the counter under `key` and return the new value.
+    ///
+    /// See [`kv_incr`](#tymethod.kv_incr).
+    fn kv_decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64>;
+
+    /// Replace the record under `key` by `new` if its value is `expected`.
+    ///
+    /// `None` stands for a missing record: `expected` as `None` only swaps if there is no such
+    /// record, and `new` as `None` removes it. On a mismatch, nothing is written and the inner
+    /// error holds the current value.
+    ///
+    /// The comparison and the write hold the write lock of the handle, also taken by
+    /// [`kv_store`](#tymethod.kv_store), [`kv_append`](#tymethod.kv_append) and
+    /// [`kv_delete`](#tymethod.kv_delete), so they are atomic with respect to every write on the
+    /// same handle. They happen within a write transaction of their own, committed on success
+    /// and rolled back on failure or mismatch: changes made since the last commit are committed
+    /// beforehand, so that a rollback only reverts this write. While a transaction begun with
+    /// [`Transaction::begin`](trait.Transaction.html#tymethod.begin) is open, they are part of
+    /// it instead, and committing or rolling back is left to the application.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// let unqlite = UnQLite::create_in_memory();
+    /// unqlite.kv_compare_and_swap("key", None, Some(b"1")).unwrap().unwrap();
+    ///
+    /// let conflict = unqlite
+    ///     .kv_compare_and_swap("key", Some(b"0"), Some(b"2"))
+    ///     .unwrap()
+    ///     .unwrap_err();
+    /// assert_eq!(conflict.current, Some(b"1".to_vec()));
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_compare_and_swap<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<::std::result::Result<(), CompareAndSwapError>>;
+
+    /// Replace the record under `key` with the result of `f` on its current value.
+    ///
+    /// `f` gets `None` if there is no such record, and returns `None` to remove it. The new
+    /// value is returned. Like [`kv_compare_and_swap`](#tymethod.kv_compare_and_swap), this is
+    /// atomic with respect to the other writes on the same handle, so `f` is called once.
+    ///
+    /// `f` runs under the write lock of the handle, which is not reentrant: writing through the
+    /// handle, or one of its buckets, from `f` deadlocks.
+    ///
+    /// ```ignore
+    /// // Count visits
+    /// unqlite.kv_update("visits", |old| {
+    ///     let count = old.map_or(0, |old| old[0]);
+    ///     Some(vec![count + 1])
+    /// })?;
+    /// ```
+    fn kv_update<K, F>(&self, key: K, f: F) -> Result<Option<Vec<u8>>>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>;
+
+    /// Combine the record under `key` with `operand`, using the merge operator registered under
+    /// `name` on the handle.
+    ///
+    /// The operator gets the current value, `None` if there is no such record, and its result
+    /// replaces it. Like [`kv_update`](#tymethod.kv_update), this is atomic with respect to the
+    /// other writes on the same handle, and the operator runs under the write lock of the handle.
+    /// Any time-to-live is kept. The name is not stored with the record, so every merge of a
+    /// record should use the same operator.
+    ///
+    /// Fails with `ErrorKind::NOTIMPLEMENTED` if no operator is registered under `name`, see
+    /// [`UnQLite::register_merge_operator`](struct.UnQLite.html#method.register_merge_operator)
+    /// and the [`merge`](merge/index.html) module.
+    fn kv_merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        name: &str,
+        operand: V,
     ) -> Result<()>;
 
     /// Configure the hash function of the underlying Key/Value (KV) storage engine.
@@ -65,6 +388,8 @@ pub trait KV {
     /// Specify a hash function to be used instead of the built-in hash function. This option
     /// accepts a single argument which is a pointer to the client hash function.
     /// Note that the built-in hash function (DJB) is recommended for most purposes.
+    ///
+    /// See [`kv_set_hash`](#tymethod.kv_set_hash) to use a Rust closure instead.
     fn kv_config_hash(
         &self,
         hash: extern "C" fn(key: *const c_void, len: u32) -> u32,
@@ -73,48 +398,289 @@ pub trait KV {
     /// Configure the compare function of the underlying Key/Value (KV) storage engine.
     ///
     /// Specify a comparison function to be used instead of the built-in comparison function. This
-    /// option accepts a single argument which is a pointer to the client comparison function.
+    /// option accepts a single argument which is a pointer to the client comparison function,
+    /// which compares `len` bytes at `lhs` and `rhs` like `memcmp`.
     /// Note that the built-in comparison function (Tuned memcmp() implementation) is recommended
     /// for most purposes.
-    fn kv_config_cmp(&self, hash: extern "C" fn(key: *const c_void, len: u32) -> u32)
-        -> Result<()>;
+    ///
+    /// See [`kv_set_cmp`](#tymethod.kv_set_cmp) to use a Rust closure instead.
+    fn kv_config_cmp(
+        &self,
+        cmp: extern "C" fn(lhs: *const c_void, rhs: *const c_void, len: u32) -> i32,
+    ) -> Result<()>;
+
+    /// Use `hash` as the hash function of the Key/Value storage engine.
+    ///
+    /// The closure is kept alive until the database is closed. Configure it before storing any
+    /// record, and use the same function each time an on-disk database is opened. Keys equal
+    /// for [`kv_set_cmp`](#tymethod.kv_set_cmp) must have the same hash. A panic in `hash`
+    /// aborts the process.
+    ///
+    /// At most 32 hash functions may be installed at the same time across every handle, then
+    /// `ErrorKind::LIMIT` is returned.
+    fn kv_set_hash<F>(&self, hash: F) -> Result<()>
+    where
+        F: Fn(&[u8]) -> u32 + Send + Sync + 'static;
+
+    /// Use `cmp` to compare keys in the Key/Value storage engine.
+    ///
+    /// The engine compares keys, or chunks of keys, of the same length, so `cmp` must also tell
+    /// chunks of equal keys equal, as a case-insensitive comparison does. The built-in `hash` and
+    /// `mem` engines only check whether keys compare equal, to find a record among those with the
+    /// same hash: the order given by `cmp` is not the order of cursors, and
+    /// [`Cursor::range`](trait.Cursor.html#tymethod.range) and
+    /// [`Cursor::scan_prefix`](trait.Cursor.html#method.scan_prefix) compare keys bytewise.
+    /// A panic in `cmp` aborts the process.
+    ///
+    /// As for [`kv_set_hash`](#tymethod.kv_set_hash), the closure is kept alive until the
+    /// database is closed and at most 32 of them may be installed at the same time.
+    fn kv_set_cmp<F>(&self, cmp: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static;
+
+    /// Use `eq` to tell whether keys are equal in the Key/Value storage engine.
+    ///
+    /// A shorthand for [`kv_set_cmp`](#tymethod.kv_set_cmp) when only equality matters, which
+    /// is all the built-in engines look at.
+    ///
+    /// ```
+    /// # extern crate unqlite;
+    /// #
+    /// use unqlite::{UnQLite, KV};
+    ///
+    /// # #[cfg(feature = "enable-threads")]
+    /// # fn main() {
+    /// // Case-insensitive ASCII keys
+    /// let unqlite = UnQLite::create_in_memory();
+    /// unqlite
+    ///     .kv_set_hash(|key| {
+    ///         key.iter()
+    ///             .fold(5381u32, |h, b| h.wrapping_mul(33) ^ b.to_ascii_lowercase() as u32)
+    ///     })
+    ///     .unwrap();
+    /// unqlite
+    ///     .kv_set_key_eq(|lhs, rhs| lhs.eq_ignore_ascii_case(rhs))
+    ///     .unwrap();
+    ///
+    /// unqlite.kv_store("Key", "value").unwrap();
+    /// assert_eq!(unqlite.kv_fetch("KEY").unwrap(), b"value");
+    /// # }
+    /// # #[cfg(not(feature = "enable-threads"))]
+    /// # fn main() { }
+    /// ```
+    fn kv_set_key_eq<F>(&self, eq: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + Sync + 'static;
 }
 
 /// Key-Value Store Interface
+///
+/// Keys of the database starting with a NUL byte are reserved for buckets and metadata: the
+/// methods of `UnQLite` fail with `ErrorKind::INVALID` for them, or tell them missing.
 impl KV for UnQLite {
     fn kv_store<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
-        let key = key.as_ref();
-        let value = value.as_ref();
-        wrap!(
-            kv_store,
-            self.as_raw_mut_ptr(),
-            key.as_ptr() as _,
-            key.len() as _,
-            value.as_ptr() as _,
-            value.len() as _
-        )
+        self.raw().kv_store(root_key(&key)?, value)
     }
 
     fn kv_append<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
-        let key = key.as_ref();
-        let value = value.as_ref();
-        wrap!(
-            kv_append,
-            self.as_raw_mut_ptr(),
-            key.as_ptr() as _,
-            key.len() as _,
-            value.as_ptr() as _,
-            value.len() as _
-        )
+        self.raw().kv_append(root_key(&key)?, value)
     }
 
     fn kv_delete<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
-        wrap!(
-            kv_delete,
-            self.as_raw_mut_ptr(),
-            key.as_ref().as_ptr() as _,
-            key.as_ref().len() as _
-        )
+        self.raw().kv_delete(root_key(&key)?)
+    }
+
+    fn kv_delete_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<usize> {
+        // Reserved keys are skipped at the root.
+        self.delete_range(&[], range)
+    }
+
+    fn kv_delete_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<usize> {
+        self.delete_range(&[], prefix_range(prefix.as_ref()))
+    }
+
+    fn kv_contains<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        root_key(&key).is_ok_and(|key| self.raw().kv_contains(key))
+    }
+
+    fn kv_fetch_length<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
+        self.raw().kv_fetch_length(root_key(&key)?)
+    }
+
+    fn kv_fetch<K: AsRef<[u8]>>(&self, key: K) -> Result<Vec<u8>> {
+        self.raw().kv_fetch(root_key(&key)?)
+    }
+
+    fn kv_fetch_into<K: AsRef<[u8]>>(&self, key: K, buf: &mut Vec<u8>) -> Result<()> {
+        self.raw().kv_fetch_into(root_key(&key)?, buf)
+    }
+
+    fn kv_fetch_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<Vec<u8>>>> {
+        let mut values = self.raw().kv_fetch_many(keys);
+        for (value, key) in values.iter_mut().zip(keys) {
+            if let Err(err) = root_key(key) {
+                *value = Err(err);
+            }
+        }
+        values
+    }
+
+    fn kv_contains_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<bool>> {
+        let mut found = self.raw().kv_contains_many(keys);
+        for (found, key) in found.iter_mut().zip(keys) {
+            if let Err(err) = root_key(key) {
+                *found = Err(err);
+            }
+        }
+        found
+    }
+
+    fn kv_fetch_callback<K, F>(&self, key: K, consumer: F) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8]) -> ControlFlow<()>,
+    {
+        self.raw().kv_fetch_callback(root_key(&key)?, consumer)
+    }
+
+    fn kv_reader<K: AsRef<[u8]>>(&self, key: K) -> Result<KvReader<'_>> {
+        KvReader::new(self, root_key(&key)?)
+    }
+
+    fn kv_writer<K: AsRef<[u8]>>(&self, key: K) -> KvWriter<'_> {
+        let mut writer = KvWriter::new(self, key.as_ref());
+        writer.invalid = root_key(&key).is_err();
+        writer
+    }
+
+    fn kv_store_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.raw().kv_store_with_ttl(root_key(&key)?, value, ttl)
+    }
+
+    fn kv_ttl<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Duration>> {
+        self.raw().kv_ttl(root_key(&key)?)
+    }
+
+    fn kv_persist<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.raw().kv_persist(root_key(&key)?)
+    }
+
+    fn kv_insert_new<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.raw().kv_insert_new(root_key(&key)?, value)
+    }
+
+    fn kv_replace<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.raw().kv_replace(root_key(&key)?, value)
+    }
+
+    fn kv_incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        self.raw().kv_incr(root_key(&key)?, delta)
+    }
+
+    fn kv_decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        self.raw().kv_decr(root_key(&key)?, delta)
+    }
+
+    fn kv_compare_and_swap<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<::std::result::Result<(), CompareAndSwapError>> {
+        self.raw()
+            .kv_compare_and_swap(root_key(&key)?, expected, new)
+    }
+
+    fn kv_update<K, F>(&self, key: K, f: F) -> Result<Option<Vec<u8>>>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        self.raw().kv_update(root_key(&key)?, f)
+    }
+
+    fn kv_merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        name: &str,
+        operand: V,
+    ) -> Result<()> {
+        self.raw().kv_merge(root_key(&key)?, name, operand)
+    }
+
+    fn kv_config_hash(
+        &self,
+        hash: extern "C" fn(key: *const c_void, len: u32) -> u32,
+    ) -> Result<()> {
+        self.raw().kv_config_hash(hash)
+    }
+
+    fn kv_config_cmp(
+        &self,
+        cmp: extern "C" fn(lhs: *const c_void, rhs: *const c_void, len: u32) -> i32,
+    ) -> Result<()> {
+        self.raw().kv_config_cmp(cmp)
+    }
+
+    fn kv_set_hash<F>(&self, hash: F) -> Result<()>
+    where
+        F: Fn(&[u8]) -> u32 + Send + Sync + 'static,
+    {
+        self.raw().kv_set_hash(hash)
+    }
+
+    fn kv_set_cmp<F>(&self, cmp: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static,
+    {
+        self.raw().kv_set_cmp(cmp)
+    }
+
+    fn kv_set_key_eq<F>(&self, eq: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.raw().kv_set_key_eq(eq)
+    }
+}
+
+/// The records of the whole database, reserved keys included, behind the `KV` interface of the
+/// database and of its buckets.
+#[derive(Clone, Copy)]
+pub(crate) struct RawKv<'db>(&'db UnQLite);
+
+impl<'db> Deref for RawKv<'db> {
+    type Target = UnQLite;
+
+    fn deref(&self) -> &UnQLite {
+        self.0
+    }
+}
+
+impl<'db> KV for RawKv<'db> {
+    fn kv_store<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.with_update_lock(|| self.kv_store_unlocked(key.as_ref(), value.as_ref()))
+    }
+
+    fn kv_append<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        self.with_update_lock(|| self.kv_append_unlocked(key.as_ref(), value.as_ref()))
+    }
+
+    fn kv_delete<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.with_update_lock(|| self.kv_delete_unlocked(key.as_ref()))
+    }
+
+    fn kv_delete_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> Result<usize> {
+        self.delete_range(&[], range)
+    }
+
+    fn kv_delete_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<usize> {
+        self.delete_range(&[], prefix_range(prefix.as_ref()))
     }
 
     fn kv_contains<K: AsRef<[u8]>>(&self, key: K) -> bool {
@@ -123,10 +689,13 @@ impl KV for UnQLite {
 
     fn kv_fetch_length<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
         let key = key.as_ref();
+        if self.has_expired(key) {
+            return Err(ErrorKind::NOTFOUND.into());
+        }
         let mut len = 0i64;
-        wrap!(
+        wrap_raw!(
+            self,
             kv_fetch,
-            self.as_raw_mut_ptr(),
             key.as_ptr() as _,
             key.len() as _,
             ptr::null_mut(),
@@ -136,87 +705,583 @@ impl KV for UnQLite {
     }
 
     fn kv_fetch<K: AsRef<[u8]>>(&self, key: K) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.kv_fetch_into(key, &mut buf).map(|_| buf)
+    }
+
+    fn kv_fetch_into<K: AsRef<[u8]>>(&self, key: K, buf: &mut Vec<u8>) -> Result<()> {
+        buf.clear();
+        // A single call through the callback sees the whole record, while fetching the length
+        // first would race with concurrent writes.
+        self.kv_fetch_callback(key, |data| {
+            buf.extend_from_slice(data);
+            ControlFlow::Continue(())
+        })
+    }
+
+    fn kv_fetch_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<Vec<u8>>>> {
+        let mut buf = Vec::new();
+        self.fetch_each(keys, |key| {
+            buf.clear();
+            with_consumer(
+                |data| {
+                    buf.extend_from_slice(data);
+                    ControlFlow::Continue(())
+                },
+                |raw, user_data| unsafe {
+                    unqlite_kv_fetch_callback(
+                        self.as_raw_mut_ptr(),
+                        key.as_ptr() as _,
+                        key.len() as i32,
+                        Some(raw),
+                        user_data,
+                    )
+                },
+            )?;
+            Ok(buf.as_slice().to_vec())
+        })
+    }
+
+    fn kv_contains_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<bool>> {
+        let found = self.fetch_each(keys, |key| {
+            let mut len = 0i64;
+            unsafe {
+                unqlite_kv_fetch(
+                    self.as_raw_mut_ptr(),
+                    key.as_ptr() as _,
+                    key.len() as _,
+                    ptr::null_mut(),
+                    &mut len,
+                )
+            }
+            .wrap()
+        });
+        found
+            .into_iter()
+            .map(|found| found.map(|found| found.is_some()))
+            .collect()
+    }
+
+    fn kv_fetch_callback<K, F>(&self, key: K, consumer: F) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8]) -> ControlFlow<()>,
+    {
         let key = key.as_ref();
-        let mut len = self.kv_fetch_length(key)?;
-        let mut buf: Vec<u8> = Vec::with_capacity(len as usize);
-        let cap = buf.capacity();
-        let ptr = buf.as_mut_ptr();
-        mem::forget(buf);
-        wrap!(
-            kv_fetch,
-            self.as_raw_mut_ptr(),
-            key.as_ptr() as _,
-            key.len() as _,
-            ptr as _,
-            &mut len
-        )
-        .map(|_| unsafe { Vec::from_raw_parts(ptr, len as usize, cap) })
+        if self.has_expired(key) {
+            return Err(ErrorKind::NOTFOUND.into());
+        }
+        with_consumer(consumer, |raw, user_data| {
+            eval_with!(
+                self.busy(),
+                kv_fetch_callback,
+                self.as_raw_mut_ptr(),
+                key.as_ptr() as _,
+                key.len() as i32,
+                Some(raw),
+                user_data
+            )
+        })
+    }
+
+    fn kv_reader<K: AsRef<[u8]>>(&self, key: K) -> Result<KvReader<'_>> {
+        KvReader::new(self.0, key.as_ref())
+    }
+
+    fn kv_writer<K: AsRef<[u8]>>(&self, key: K) -> KvWriter<'_> {
+        KvWriter::new(self.0, key.as_ref())
     }
 
-    fn kv_fetch_callback<K: AsRef<[u8]>>(
+    fn kv_store_with_ttl<K: AsRef<[u8]>, V: AsRef<[u8]>>(
         &self,
         key: K,
-        consumer: extern "C" fn(data: *const c_void, len: u32, user_data: *mut c_void) -> i32,
+        value: V,
+        ttl: Duration,
     ) -> Result<()> {
         let key = key.as_ref();
-        wrap!(
-            kv_fetch_callback,
-            self.as_raw_mut_ptr(),
-            key.as_ptr() as _,
-            key.len() as i32,
-            Some(consumer),
-            ptr::null_mut()
-        )
+        self.locked_update(|| {
+            self.kv_store_unlocked(key, value.as_ref())?;
+            self.set_ttl(key, ttl)
+        })
+    }
+
+    fn kv_ttl<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Duration>> {
+        let key = key.as_ref();
+        self.kv_fetch_length(key)?;
+        Ok(self.ttl_of(key))
+    }
+
+    fn kv_persist<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        let key = key.as_ref();
+        self.locked_update(|| {
+            self.kv_fetch_length(key)?;
+            if self.ttl_enabled() {
+                self.clear_ttl(key)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn kv_insert_new<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        let key = key.as_ref();
+        self.locked_update(|| {
+            if not_found_as_none(self.kv_fetch_length(key))?.is_some() {
+                return Err(ErrorKind::EXISTS.into());
+            }
+            self.kv_store_unlocked(key, value.as_ref())
+        })
+    }
+
+    fn kv_replace<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<()> {
+        let key = key.as_ref();
+        self.locked_update(|| {
+            self.kv_fetch_length(key)?;
+            self.kv_store_unlocked(key, value.as_ref())
+        })
+    }
+
+    fn kv_incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        let key = key.as_ref();
+        self.locked_update(|| {
+            // An expired record does not pass its time-to-live on.
+            self.expire(key);
+            let current = match self.kv_fetch_opt(key)? {
+                Some(value) => value
+                    .as_slice()
+                    .try_into()
+                    .map(i64::from_le_bytes)
+                    .map_err(|_| CounterError::NotACounter { len: value.len() })?,
+                None => 0,
+            };
+            let value = current
+                .checked_add(delta)
+                .ok_or(CounterError::Overflow)?;
+            self.kv_store_keep_ttl(key, &value.to_le_bytes())?;
+            Ok(value)
+        })
+    }
+
+    fn kv_decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64> {
+        let delta = delta.checked_neg().ok_or(CounterError::Overflow)?;
+        self.kv_incr(key, delta)
+    }
+
+    fn kv_compare_and_swap<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<::std::result::Result<(), CompareAndSwapError>> {
+        let key = key.as_ref();
+        let swap = || {
+            let current = self.kv_fetch_opt(key)?;
+            if current.as_deref() != expected {
+                return Ok(Err(CompareAndSwapError {
+                    current,
+                    proposed: new.map(|new| new.to_vec()),
+                }));
+            }
+            self.kv_put_opt(key, current.is_some(), new)?;
+            Ok(Ok(()))
+        };
+        // Nothing was written on a mismatch, still leave pending changes as they were.
+        self.with_update_lock(|| self.update_in_transaction(swap, |swapped| swapped.is_ok()))
+    }
+
+    fn kv_update<K, F>(&self, key: K, f: F) -> Result<Option<Vec<u8>>>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let key = key.as_ref();
+        self.locked_update(|| {
+            let current = self.kv_fetch_opt(key)?;
+            let new = f(current.as_deref());
+            self.kv_put_opt(key, current.is_some(), new.as_deref())?;
+            Ok(new)
+        })
+    }
+
+    fn kv_merge<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        name: &str,
+        operand: V,
+    ) -> Result<()> {
+        let operator = self
+            .merge_operator(name)
+            .ok_or(ErrorKind::NOTIMPLEMENTED)?;
+        let key = key.as_ref();
+        self.locked_update(|| {
+            self.expire(key);
+            let current = self.kv_fetch_opt(key)?;
+            let value = operator.merge(current.as_deref(), operand.as_ref())?;
+            self.kv_store_keep_ttl(key, &value)
+        })
     }
 
     fn kv_config_hash(
         &self,
         hash: extern "C" fn(key: *const c_void, len: u32) -> u32,
     ) -> Result<()> {
-        wrap!(
+        wrap_raw!(
+            self,
             kv_config,
-            self.as_raw_mut_ptr(),
             UNQLITE_KV_CONFIG_HASH_FUNC,
             hash
-        )
+        )?;
+        self.kv_fns().hash = None;
+        Ok(())
     }
 
-    fn kv_config_cmp(&self, cmp: extern "C" fn(key: *const c_void, len: u32) -> u32) -> Result<()> {
-        wrap!(
+    fn kv_config_cmp(
+        &self,
+        cmp: extern "C" fn(lhs: *const c_void, rhs: *const c_void, len: u32) -> i32,
+    ) -> Result<()> {
+        wrap_raw!(
+            self,
             kv_config,
-            self.as_raw_mut_ptr(),
             UNQLITE_KV_CONFIG_CMP_FUNC,
             cmp
+        )?;
+        self.kv_fns().cmp = None;
+        Ok(())
+    }
+
+    fn kv_set_hash<F>(&self, hash: F) -> Result<()>
+    where
+        F: Fn(&[u8]) -> u32 + Send + Sync + 'static,
+    {
+        let slot = HashSlot::claim(Box::new(hash))?;
+        wrap_raw!(
+            self,
+            kv_config,
+            UNQLITE_KV_CONFIG_HASH_FUNC,
+            slot.raw()
+        )?;
+        // The previous function, if any, is not called anymore.
+        self.kv_fns().hash = Some(slot);
+        Ok(())
+    }
+
+    fn kv_set_cmp<F>(&self, cmp: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static,
+    {
+        let slot = CmpSlot::claim(Box::new(cmp))?;
+        wrap_raw!(
+            self,
+            kv_config,
+            UNQLITE_KV_CONFIG_CMP_FUNC,
+            slot.raw()
+        )?;
+        self.kv_fns().cmp = Some(slot);
+        Ok(())
+    }
+
+    fn kv_set_key_eq<F>(&self, eq: F) -> Result<()>
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.kv_set_cmp(move |lhs, rhs| {
+            if eq(lhs, rhs) {
+                Ordering::Equal
+            } else {
+                Ordering::Greater
+            }
+        })
+    }
+}
+
+impl UnQLite {
+    /// The `KV` interface over every record, reserved keys included.
+    pub(crate) fn raw(&self) -> RawKv<'_> {
+        RawKv(self)
+    }
+
+    /// Run `f` under the update lock of the handle, within a write transaction committed if `f`
+    /// succeeds and rolled back otherwise, see `update_in_transaction`.
+    pub(crate) fn locked_update<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        self.with_update_lock(|| self.update_in_transaction(f, |_| true))
+    }
+
+    /// Like `locked_update`, unless a transaction begun with `Transaction::begin` is open: then
+    /// nothing is done and `None` is returned, as committing would end that transaction.
+    pub(crate) fn locked_update_idle<T, F>(&self, f: F) -> Result<Option<T>>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        self.with_update_lock(|| {
+            if self.in_transaction() {
+                return Ok(None);
+            }
+            self.update_in_transaction(f, |_| true).map(Some)
+        })
+    }
+
+    /// Run `f` within a write transaction, only committed if `commit` holds for its result.
+    ///
+    /// The engine has a single write transaction per handle. Pending changes are committed
+    /// first, so that a rollback only reverts those of `f`. While a transaction begun with
+    /// `Transaction::begin` is open, `f` runs within it and neither commits nor rolls back.
+    fn update_in_transaction<T, F, C>(&self, f: F, commit: C) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+        C: FnOnce(&T) -> bool,
+    {
+        if self.in_transaction() {
+            return f();
+        }
+        wrap_raw!(self, commit)?;
+        wrap_raw!(self, begin)?;
+        match f() {
+            // `commit` rolls back by itself on failure.
+            Ok(value) if commit(&value) => self.commit().map(|_| value),
+            result => {
+                let _ = self.rollback();
+                result
+            }
+        }
+    }
+
+    /// Run `f` under the update lock of the handle.
+    pub(crate) fn with_update_lock<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let _guard = self
+            .update_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        f()
+    }
+
+    /// Run `fetch` on each of `keys` in turn, within a single busy loop: a key finding the
+    /// database locked is retried from there, and once the policy gives up, that key and the
+    /// ones after it fail with the same error. Missing and expired records are `None`.
+    fn fetch_each<K, T, F>(&self, keys: &[K], mut fetch: F) -> Vec<Result<Option<T>>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(&[u8]) -> Result<T>,
+    {
+        let mut results = Vec::with_capacity(keys.len());
+        let rc = self.busy().eval(|| {
+            while let Some(key) = keys.get(results.len()) {
+                let key = key.as_ref();
+                let result = if self.has_expired(key) {
+                    Err(ErrorKind::NOTFOUND.into())
+                } else {
+                    fetch(key)
+                };
+                match result {
+                    Err(ref err) if is_busy(err) => return err.code(),
+                    result => results.push(not_found_as_none(result)),
+                }
+            }
+            UNQLITE_OK
+        });
+        while results.len() < keys.len() {
+            results.push(rc.wrap().map(|_| None));
+        }
+        results
+    }
+
+    /// Remove the records under `prefix` whose key, without the prefix, is within `range`, and
+    /// return how many were removed.
+    ///
+    /// The keys are listed as by [`Iter`](struct.Iter.html), seeking to the start of the range on
+    /// ordered engines, so reserved keys are left alone at the root.
+    /// Records expiring before their turn are skipped. Fails with `ErrorKind::NOTIMPLEMENTED` if
+    /// the engine is not transactional, as the removal would not be atomic.
+    pub(crate) fn delete_range<K, R>(&self, prefix: &[u8], range: R) -> Result<usize>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.check_transactional()?;
+        self.locked_update(|| {
+            let keys = Iter::range(self, prefix.to_vec(), range)
+                .keys()
+                .collect::<Result<Vec<_>>>()?;
+            let mut full = prefix.to_vec();
+            let mut removed = 0;
+            for key in &keys {
+                full.truncate(prefix.len());
+                full.extend_from_slice(key);
+                if not_found_as_none(self.kv_delete_unlocked(&full))?.is_some() {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })
+    }
+
+    /// `kv_store`, to be called under the update lock.
+    pub(crate) fn kv_store_unlocked(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        // Cleared first, a failure never leaves the new value with the time-to-live of the old
+        // one.
+        if self.ttl_enabled() {
+            self.clear_ttl(key)?;
+        }
+        self.kv_store_keep_ttl(key, value)
+    }
+
+    /// `kv_append`, to be called under the update lock.
+    pub(crate) fn kv_append_unlocked(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.expire(key);
+        wrap_raw!(
+            self,
+            kv_append,
+            key.as_ptr() as _,
+            key.len() as _,
+            value.as_ptr() as _,
+            value.len() as _
         )
     }
+
+    /// `kv_delete`, to be called under the update lock.
+    pub(crate) fn kv_delete_unlocked(&self, key: &[u8]) -> Result<()> {
+        if self.expire(key) {
+            return Err(ErrorKind::NOTFOUND.into());
+        }
+        wrap_raw!(self, kv_delete, key.as_ptr() as _, key.len() as _)?;
+        if self.ttl_enabled() {
+            self.clear_ttl(key)?;
+        }
+        Ok(())
+    }
+
+    /// Store `value` under `key`, keeping any time-to-live.
+    fn kv_store_keep_ttl(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        wrap_raw!(
+            self,
+            kv_store,
+            key.as_ptr() as _,
+            key.len() as _,
+            value.as_ptr() as _,
+            value.len() as _
+        )
+    }
+
+    /// Fetch the record under `key`, `None` if there is no such record.
+    fn kv_fetch_opt(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        not_found_as_none(self.raw().kv_fetch(key))
+    }
+
+    /// Store `value` under `key`, or remove the record if `value` is `None` and it `exists`.
+    fn kv_put_opt(&self, key: &[u8], exists: bool, value: Option<&[u8]>) -> Result<()> {
+        match value {
+            Some(value) => self.kv_store_unlocked(key, value),
+            None if exists => self.kv_delete_unlocked(key),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Prefix of the keys reserved for buckets and metadata, see [`Bucket`](struct.Bucket.html).
+pub(crate) const RESERVED: &[u8] = b"\0";
+
+/// `key` of the database, failing with `ErrorKind::INVALID` if it is reserved.
+pub(crate) fn root_key<K: AsRef<[u8]>>(key: &K) -> Result<&[u8]> {
+    let key = key.as_ref();
+    if key.starts_with(RESERVED) {
+        Err(ErrorKind::INVALID.into())
+    } else {
+        Ok(key)
+    }
+}
+
+/// Turn `ErrorKind::NOTFOUND` into `None`.
+/// Whether `err` tells the database is locked, to be retried as the busy policy decides.
+fn is_busy(err: &Error) -> bool {
+    matches!(err.kind(), Some(ErrorKind::LOCKED) | Some(ErrorKind::BUSY))
+}
+
+fn not_found_as_none<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ref err) if err.kind() == Some(ErrorKind::NOTFOUND) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// The value did not match, see
+/// [`KV::kv_compare_and_swap`](trait.KV.html#tymethod.kv_compare_and_swap).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompareAndSwapError {
+    /// The current value, `None` if there is no such record
+    pub current: Option<Vec<u8>>,
+    /// The value which was not written, `None` for a removal
+    pub proposed: Option<Vec<u8>>,
+}
+
+impl fmt::Display for CompareAndSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.current {
+            Some(_) => write!(f, "compare and swap conflict: the value changed"),
+            None => write!(f, "compare and swap conflict: no such record"),
+        }
+    }
+}
+
+impl error::Error for CompareAndSwapError {}
+
+/// Error of [`KV::kv_incr`](trait.KV.html#tymethod.kv_incr), reported within `Error::Other`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CounterError {
+    /// The record is not a counter, it is `len` bytes long
+    NotACounter { len: usize },
+    /// The counter would overflow an `i64`
+    Overflow,
+}
+
+impl fmt::Display for CounterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CounterError::NotACounter { len } => {
+                write!(f, "not a counter: {} bytes instead of 8", len)
+            }
+            CounterError::Overflow => write!(f, "counter overflow"),
+        }
+    }
+}
+
+impl error::Error for CounterError {}
+
+impl From<CounterError> for Error {
+    fn from(err: CounterError) -> Error {
+        Error::Other(Box::new(err))
+    }
 }
 
 #[cfg(test)]
 #[cfg(feature = "enable-threads")]
 mod tests {
-    use super::KV;
-    use crate::UnQLite;
+    use super::{CounterError, KV};
+    use crate::merge::{MaxI64, SumI64};
+    use crate::{Error, ErrorKind, Transaction, UnQLite};
+    use std::ops::ControlFlow;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
     #[test]
     fn test_kv_store() {
         let unqlite = UnQLite::create_temp();
-        let _ = unqlite.kv_store("abc", "123").unwrap();
+        unqlite.kv_store("abc", "123").unwrap();
         let vec = [1u8, 2u8, 3u8];
-        let _ = unqlite.kv_store(&vec, "123").unwrap();
-        let _ = unqlite
-            .kv_store(&vec![4, 5, 6], &String::from("哈哈"))
+        unqlite.kv_store(vec, "123").unwrap();
+        unqlite
+            .kv_store(vec![4, 5, 6], String::from("哈哈"))
             .unwrap();
 
         let value = unqlite.kv_fetch_length("abc");
         assert!(value.is_ok());
         assert!(value.unwrap() == 3);
-        assert!(unqlite.kv_contains(&vec![1, 2, 3]));
+        assert!(unqlite.kv_contains(vec![1, 2, 3]));
 
-        let value = unqlite.kv_fetch(&vec![1, 2, 3]).unwrap();
+        let value = unqlite.kv_fetch(vec![1, 2, 3]).unwrap();
         assert!(value.len() == 3);
         assert_eq!(value, [49, 50, 51]);
 
-        let value = unqlite.kv_fetch(&vec![4, 5, 6]).unwrap();
+        let value = unqlite.kv_fetch(vec![4, 5, 6]).unwrap();
         assert_eq!(
             unsafe { String::from_utf8_unchecked(value) },
             String::from("哈哈")
@@ -225,14 +1290,312 @@ mod tests {
         unqlite.kv_delete("abc").unwrap();
         assert!(!unqlite.kv_contains("abc"));
 
-        unqlite.kv_append(&vec, "456").unwrap();
-        assert!(unqlite.kv_fetch_length(&vec).unwrap() == 6);
+        unqlite.kv_append(vec, "456").unwrap();
+        assert!(unqlite.kv_fetch_length(vec).unwrap() == 6);
+    }
+
+    #[test]
+    fn kv_fetch_callback() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("key", "value").unwrap();
+
+        let mut buf = Vec::new();
+        unqlite
+            .kv_fetch_callback("key", |data| {
+                buf.extend_from_slice(data);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(buf, b"value");
+
+        unqlite
+            .kv_fetch_callback("key", |_| ControlFlow::Break(()))
+            .unwrap();
+        assert!(unqlite
+            .kv_fetch_callback("missing", |_| ControlFlow::Continue(()))
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "consumer panic")]
+    fn kv_fetch_callback_panic() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("key", "value").unwrap();
+        let _ = unqlite.kv_fetch_callback("key", |_| panic!("consumer panic"));
+    }
+
+    #[test]
+    fn kv_set_key_eq() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite
+            .kv_set_hash(|key| key.iter().map(|b| b.to_ascii_lowercase() as u32).sum())
+            .unwrap();
+        unqlite
+            .kv_set_key_eq(|lhs, rhs| lhs.eq_ignore_ascii_case(rhs))
+            .unwrap();
+
+        unqlite.kv_store("Key", "value").unwrap();
+        unqlite.kv_store("KEY", "other").unwrap();
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"other");
+        assert!(!unqlite.kv_contains("keys"));
+    }
+
+    #[test]
+    fn kv_set_cmp() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite
+            .kv_set_hash(|key| key.iter().map(|b| b.to_ascii_lowercase() as u32).sum())
+            .unwrap();
+        unqlite
+            .kv_set_cmp(|lhs, rhs| lhs.to_ascii_lowercase().cmp(&rhs.to_ascii_lowercase()))
+            .unwrap();
+
+        unqlite.kv_store("Key", "value").unwrap();
+        assert_eq!(unqlite.kv_fetch("kEY").unwrap(), b"value");
+        assert!(!unqlite.kv_contains("yek"));
+    }
+
+    #[test]
+    fn kv_insert_new_replace() {
+        let unqlite = UnQLite::create_in_memory();
+        let err = unqlite.kv_replace("key", "value").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::NOTFOUND));
+        assert!(!unqlite.kv_contains("key"));
+
+        unqlite.kv_insert_new("key", "first").unwrap();
+        let err = unqlite.kv_insert_new("key", "second").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::EXISTS));
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"first");
+
+        unqlite.kv_replace("key", "second").unwrap();
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"second");
+    }
+
+    #[test]
+    fn kv_fetch_concurrent() {
+        let unqlite = Arc::new(UnQLite::create_in_memory());
+        unqlite.kv_store("key", "").unwrap();
+        let writer = {
+            let unqlite = unqlite.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    unqlite.kv_append("key", "abcd").unwrap();
+                }
+            })
+        };
+        let mut buf = Vec::new();
+        for _ in 0..1000 {
+            unqlite.kv_fetch_into("key", &mut buf).unwrap();
+            assert_eq!(buf.len() % 4, 0);
+            assert!(buf.chunks(4).all(|chunk| chunk == b"abcd"));
+        }
+        writer.join().unwrap();
+        assert_eq!(unqlite.kv_fetch("key").unwrap().len(), 4000);
+    }
+
+    #[test]
+    fn kv_delete_range() {
+        let unqlite = UnQLite::create_in_memory();
+        for key in &["a", "b", "ba", "bb", "c", "d"] {
+            unqlite.kv_store(key, "value").unwrap();
+        }
+        unqlite
+            .kv_store_with_ttl("e", "value", Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(unqlite.kv_delete_range("b".."c").unwrap(), 3);
+        assert_eq!(unqlite.kv_delete_range("c"..="d").unwrap(), 2);
+        assert_eq!(unqlite.kv_delete_range("x"..).unwrap(), 0);
+        assert!(unqlite.kv_contains("a"));
+        assert!(!unqlite.kv_contains("ba"));
+
+        unqlite.kv_store("prefix/1", "value").unwrap();
+        unqlite.kv_store("prefix/2", "value").unwrap();
+        assert_eq!(unqlite.kv_delete_prefix("prefix/").unwrap(), 2);
+
+        // Only "a" and "e" are left, the time-to-live of "e" is kept until it is removed, and
+        // "f" expires before its turn without failing the removal.
+        unqlite
+            .kv_store_with_ttl("f", "value", Duration::from_millis(1))
+            .unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert!(unqlite.kv_ttl("e").unwrap().is_some());
+        assert_eq!(unqlite.kv_delete_range::<&str, _>(..).unwrap(), 2);
+        assert!(!unqlite.kv_contains("e"));
+    }
+
+    #[test]
+    fn kv_fetch_many() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("a", "1").unwrap();
+        unqlite.kv_store("c", "").unwrap();
+
+        let keys = ["a", "b", "c"];
+        let values: Vec<_> = unqlite
+            .kv_fetch_many(&keys)
+            .into_iter()
+            .map(|value| value.unwrap())
+            .collect();
+        assert_eq!(values, [Some(b"1".to_vec()), None, Some(Vec::new())]);
+
+        let found: Vec<_> = unqlite
+            .kv_contains_many(&keys)
+            .into_iter()
+            .map(|found| found.unwrap())
+            .collect();
+        assert_eq!(found, [true, false, true]);
+        assert!(unqlite.kv_fetch_many::<&str>(&[]).is_empty());
+
+        unqlite
+            .kv_update("b", |_| {
+                let values = unqlite.kv_fetch_many(&["a", "c"]);
+                let mut joined = values[0].as_ref().unwrap().clone().unwrap();
+                joined.extend(values[1].as_ref().unwrap().as_ref().unwrap());
+                Some(joined)
+            })
+            .unwrap();
+        assert_eq!(unqlite.kv_fetch("b").unwrap(), b"1");
+    }
+
+    #[test]
+    fn kv_incr() {
+        let unqlite = UnQLite::create_in_memory();
+        assert_eq!(unqlite.kv_incr("counter", 5).unwrap(), 5);
+        assert_eq!(unqlite.kv_decr("counter", 7).unwrap(), -2);
+        assert_eq!(unqlite.kv_fetch("counter").unwrap(), (-2i64).to_le_bytes());
+
+        unqlite.kv_store("text", "abc").unwrap();
+        match unqlite.kv_incr("text", 1).unwrap_err() {
+            Error::Other(err) => assert_eq!(
+                err.downcast_ref::<CounterError>(),
+                Some(&CounterError::NotACounter { len: 3 })
+            ),
+            err => panic!("unexpected error {}", err),
+        }
+        assert_eq!(unqlite.kv_fetch("text").unwrap(), b"abc");
+
+        unqlite.kv_incr("max", i64::MAX).unwrap();
+        assert!(unqlite.kv_incr("max", 1).is_err());
+        assert!(unqlite.kv_decr("min", i64::MIN).is_err());
+    }
+
+    #[test]
+    fn kv_compare_and_swap() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite
+            .kv_compare_and_swap("key", None, Some(b"1"))
+            .unwrap()
+            .unwrap();
+        let conflict = unqlite
+            .kv_compare_and_swap("key", None, Some(b"2"))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(conflict.current, Some(b"1".to_vec()));
+        assert_eq!(conflict.proposed, Some(b"2".to_vec()));
+
+        unqlite
+            .kv_compare_and_swap("key", Some(b"1"), Some(b"2"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"2");
+        unqlite
+            .kv_compare_and_swap("key", Some(b"2"), None)
+            .unwrap()
+            .unwrap();
+        assert!(!unqlite.kv_contains("key"));
+
+        let conflict = unqlite
+            .kv_compare_and_swap("key", Some(b"2"), None)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(conflict.current, None);
+    }
+
+    #[test]
+    fn conditional_writes_transaction() {
+        let unqlite = UnQLite::create_temp();
+        unqlite.kv_store("key", "value").unwrap();
+        unqlite.commit().unwrap();
+
+        // A failed write only rolls back its own changes.
+        unqlite.kv_store("pending", "value").unwrap();
+        let err = unqlite.kv_insert_new("key", "other").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::EXISTS));
+        assert!(unqlite.kv_contains("pending"));
+
+        // Within the transaction of the application, which rolls them back.
+        unqlite.begin().unwrap();
+        unqlite.kv_insert_new("new", "value").unwrap();
+        assert_eq!(unqlite.kv_incr("counter", 1).unwrap(), 1);
+        assert!(unqlite.kv_insert_new("key", "other").is_err());
+        assert!(unqlite.kv_contains("new"));
+        unqlite.rollback().unwrap();
+        assert!(!unqlite.kv_contains("new"));
+        assert!(!unqlite.kv_contains("counter"));
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"value");
+    }
+
+    #[test]
+    fn kv_update() {
+        let unqlite = Arc::new(UnQLite::create_in_memory());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let unqlite = unqlite.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        unqlite
+                            .kv_update("counter", |old| {
+                                let count = old.map_or(0, |old| {
+                                    u32::from_le_bytes([old[0], old[1], old[2], old[3]])
+                                });
+                                Some((count + 1).to_le_bytes().to_vec())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(unqlite.kv_fetch("counter").unwrap(), 400u32.to_le_bytes());
+
+        assert_eq!(unqlite.kv_update("counter", |_| None).unwrap(), None);
+        assert!(!unqlite.kv_contains("counter"));
+    }
+
+    #[test]
+    fn kv_merge() {
+        let unqlite = UnQLite::create_in_memory();
+        let err = unqlite
+            .kv_merge("max", "max_i64", 1i64.to_le_bytes())
+            .unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::NOTIMPLEMENTED));
+
+        unqlite.register_merge_operator(MaxI64);
+        unqlite.register_merge_operator(SumI64);
+        unqlite
+            .kv_store_with_ttl("max", 3i64.to_le_bytes(), Duration::from_secs(3600))
+            .unwrap();
+        for i in [2i64, 5, 4] {
+            unqlite.kv_merge("max", "max_i64", i.to_le_bytes()).unwrap();
+            unqlite.kv_merge("sum", "sum_i64", i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(unqlite.kv_fetch("max").unwrap(), 5i64.to_le_bytes());
+        assert_eq!(unqlite.kv_fetch("sum").unwrap(), 11i64.to_le_bytes());
+        assert!(unqlite.kv_ttl("max").unwrap().is_some());
+        assert_eq!(unqlite.merge_operator("max_i64").unwrap().name(), "max_i64");
+        assert!(unqlite.merge_operator("min_i64").is_none());
+
+        unqlite.kv_store("max", "abc").unwrap();
+        assert!(unqlite.kv_merge("max", "max_i64", 1i64.to_le_bytes()).is_err());
+        assert_eq!(unqlite.kv_fetch("max").unwrap(), b"abc");
     }
 
     #[test]
     #[should_panic]
     fn panic_kv_fetch_not_found() {
         let unqlite = UnQLite::create_in_memory();
-        unqlite.kv_fetch(&vec![4, 5, 6]).unwrap();
+        unqlite.kv_fetch(vec![4, 5, 6]).unwrap();
     }
 }
@@ -79,12 +79,17 @@
 extern crate tempfile;
 
 use error::Wrap;
-pub use error::{Error, Result};
+pub use error::{Error, ErrorKind, Result};
 
 use ffi::{unqlite_close, unqlite_open};
+use kv_fn::KvFns;
+use merge::MergeOperator;
+use std::collections::HashMap;
 use std::ffi::CString;
-use std::mem;
-use std::ptr::NonNull;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicBool, AtomicU8};
+use std::sync::{Arc, Mutex, MutexGuard};
+use vars::{UNQLITE_OPEN_CREATE, UNQLITE_OPEN_MMAP, UNQLITE_OPEN_READONLY, UNQLITE_OPEN_TEMP_DB};
 
 /// UnQLite database entry point.
 ///
@@ -99,20 +104,35 @@ use std::ptr::NonNull;
 /// [`open_mmap`](#method.open_mmap) | Obtain a read-only memory view of the whole database.
 /// [`open_readonly`](#method.open_readonly) | Open the database in a read-only mode.
 ///
+/// These constructors panic on failure. Use [`OpenOptions`](struct.OpenOptions.html) to get
+/// any other combination of open flags and handle errors instead.
 pub struct UnQLite {
     engine: NonNull<crate::ffi::unqlite>,
+    busy: BusyPolicy,
+    /// Rust functions the engine calls, released after closing
+    kv_fns: Mutex<KvFns>,
+    /// Serializes writes, see `KV::kv_compare_and_swap`
+    update_lock: Mutex<()>,
+    /// A transaction begun with `Transaction::begin` is open
+    transaction: AtomicBool,
+    /// Whether records may expire, see `ttl_enabled`
+    ttl: AtomicU8,
+    /// Operators of `kv_merge`, by name
+    merge: Mutex<HashMap<String, Arc<dyn MergeOperator>>>,
+}
+
+/// Call `unqlite_$i`, retrying `UNQLITE_LOCKED`/`UNQLITE_BUSY` as told by a `BusyPolicy`.
+macro_rules! eval_with {
+    ($busy: expr, $i: ident, $($e: expr),*) => (
+        $busy.eval(|| unsafe {
+            paste::expr! { [<unqlite_ $i>]($($e),*) }
+        })
+    );
 }
 
 macro_rules! eval {
     ($i: ident, $($e: expr),*) => (
-        loop {
-            match unsafe {
-                paste::expr! { [<unqlite_ $i>]($($e),*) }
-            } {
-                crate::vars::UNQLITE_LOCKED => {},
-                state => break state
-            }
-        }
+        eval_with!(crate::busy::BusyPolicy::Spin, $i, $($e),*)
     );
 }
 
@@ -120,30 +140,39 @@ macro_rules! wrap {
     ($i: ident, $($e: expr),*) => (eval!($i, $($e),*).wrap());
 }
 
+/// Like `wrap!`, but pass the raw pointer of `$self_` first and honour its busy policy.
 macro_rules! wrap_raw {
     ($self_:ident, $i: ident) => (
-        wrap!($i, $self_.as_raw_mut_ptr())
+        eval_with!($self_.busy(), $i, $self_.as_raw_mut_ptr()).wrap()
     );
     ($self_:ident, $i: ident, $($e: expr),+) => (
-        wrap!($i, $self_.as_raw_mut_ptr(), $($e),+)
+        eval_with!($self_.busy(), $i, $self_.as_raw_mut_ptr(), $($e),+).wrap()
     );
 }
 
 impl UnQLite {
-    /// Create UnQLite database at specific path.
+    /// Create UnQLite database at specific path with raw `UNQLITE_OPEN_*` flags.
     ///
     /// ```ignore
-    /// let _ = UnQLite::open("str");
-    /// let _ = UnQLite::open(String::new());
+    /// let _ = UnQLite::open("str", UNQLITE_OPEN_CREATE, BusyPolicy::default());
+    /// let _ = UnQLite::open(String::new(), UNQLITE_OPEN_TEMP_DB, BusyPolicy::default());
     /// ```
     #[inline]
-    fn open<P: AsRef<str>>(filename: P, mode: OpenMode) -> Result<UnQLite> {
-        let mut db: *mut crate::ffi::unqlite = unsafe { mem::MaybeUninit::uninit().assume_init() };
+    fn open<P: AsRef<str>>(filename: P, mode: u32, busy: BusyPolicy) -> Result<UnQLite> {
+        let mut db: *mut crate::ffi::unqlite = ptr::null_mut();
         let filename = filename.as_ref();
         let filename = CString::new(filename)?;
-        wrap!(open, &mut db, filename.as_ptr(), mode.into()).map(|_| UnQLite {
-            engine: unsafe { NonNull::new_unchecked(db) },
-        })
+        eval_with!(busy, open, &mut db, filename.as_ptr(), mode)
+            .wrap()
+            .map(|_| UnQLite {
+                engine: unsafe { NonNull::new_unchecked(db) },
+                busy,
+                kv_fns: Mutex::default(),
+                update_lock: Mutex::default(),
+                transaction: AtomicBool::new(false),
+                ttl: AtomicU8::new(ttl::TTL_UNKNOWN),
+                merge: Mutex::default(),
+            })
     }
 
     /// Create UnQLite database as `filename`.
@@ -173,7 +202,7 @@ impl UnQLite {
     /// ```
     #[inline]
     pub fn create<P: AsRef<str>>(filename: P) -> UnQLite {
-        Self::open(filename, OpenMode::Create).unwrap()
+        Self::open(filename, UNQLITE_OPEN_CREATE, BusyPolicy::default()).unwrap()
     }
 
     /// Create database in memory.
@@ -208,7 +237,7 @@ impl UnQLite {
     /// ```
     #[inline]
     pub fn create_temp() -> UnQLite {
-        Self::open("", OpenMode::TempDB).unwrap()
+        Self::open("", UNQLITE_OPEN_TEMP_DB, BusyPolicy::default()).unwrap()
     }
 
     /// Obtain a read-only memory view of the whole database.
@@ -227,7 +256,12 @@ impl UnQLite {
     /// ```
     #[inline]
     pub fn open_mmap<P: AsRef<str>>(filename: P) -> UnQLite {
-        Self::open(filename, OpenMode::MMap).unwrap()
+        Self::open(
+            filename,
+            UNQLITE_OPEN_MMAP | UNQLITE_OPEN_READONLY,
+            BusyPolicy::default(),
+        )
+        .unwrap()
     }
 
     /// Open the database in a read-only mode.
@@ -247,7 +281,24 @@ impl UnQLite {
     /// ```
     #[inline]
     pub fn open_readonly<P: AsRef<str>>(filename: P) -> UnQLite {
-        Self::open(filename, OpenMode::ReadOnly).unwrap()
+        Self::open(filename, UNQLITE_OPEN_READONLY, BusyPolicy::default()).unwrap()
+    }
+
+    /// Set the policy applied when the database is locked or busy.
+    ///
+    /// See [`BusyPolicy`](enum.BusyPolicy.html) for the available strategies. Defaults to
+    /// `BusyPolicy::Timeout` of five seconds.
+    pub fn busy_policy(mut self, policy: BusyPolicy) -> Self {
+        self.busy = policy;
+        self
+    }
+
+    pub(crate) fn busy(&self) -> &BusyPolicy {
+        &self.busy
+    }
+
+    pub(crate) fn kv_fns(&self) -> MutexGuard<'_, KvFns> {
+        self.kv_fns.lock().unwrap()
     }
 
     fn close(&self) -> Result<()> {
@@ -270,23 +321,41 @@ impl Drop for UnQLite {
 
 #[allow(dead_code, non_snake_case, non_camel_case_types)]
 pub mod ffi;
-#[allow(dead_code)]
+#[allow(dead_code, clippy::redundant_static_lifetimes)]
 pub mod vars;
 
+mod batch;
+mod bucket;
+mod busy;
+mod callback;
 mod config;
 pub mod document;
+pub mod engine;
 mod error;
 mod kv_cursor;
+mod kv_fn;
 mod kv_store;
+pub mod lib_config;
+pub mod memory;
+pub mod merge;
 mod openmode;
+mod stream;
 mod transaction;
+pub mod ttl;
+pub mod typed;
 mod util;
+pub mod vfs;
 
+pub use self::batch::{BatchError, BatchOp, WriteBatch};
+pub use self::bucket::Bucket;
+pub use self::busy::{Busy, BusyPolicy};
 pub use self::config::Config;
 pub use self::kv_cursor::*;
 pub use self::kv_store::*;
-use self::openmode::OpenMode;
+pub use self::openmode::OpenOptions;
+pub use self::stream::{KvReader, KvWriter};
 pub use self::transaction::Transaction;
+pub use self::typed::TypedStore;
 pub use self::util::*;
 
 #[cfg(test)]
@@ -0,0 +1,346 @@
+//! Library-wide configuration.
+//!
+//! A few settings of UnQLite apply to the whole process and must be chosen before the library
+//! is initialized, which happens when the first database is opened.
+//! [`LibConfig`](struct.LibConfig.html) applies them and initializes the library right away, so
+//! they cannot change afterwards:
+//!
+//! ```no_run
+//! # extern crate unqlite;
+//! #
+//! use unqlite::lib_config::{self, LibConfig, ThreadLevel};
+//! use unqlite::UnQLite;
+//!
+//! # fn main() {
+//! LibConfig::new()
+//!     .page_size(8192)
+//!     .thread_level(ThreadLevel::Multi)
+//!     .init()
+//!     .unwrap();
+//! assert_eq!(lib_config::info().page_size, 8192);
+//!
+//! let unqlite = UnQLite::create_in_memory();
+//! # }
+//! ```
+//!
+//! Since `init` initializes the library, [`vfs::register`](../vfs/fn.register.html),
+//! [`engine::register`](../engine/fn.register.html) and
+//! [`Memory::install`](../memory/struct.Memory.html#method.install) must be called before it.
+
+use crate::error::{ErrorKind, Result, Wrap};
+use crate::ffi::{
+    unqlite_lib_config, unqlite_lib_init, unqlite_lib_is_threadsafe, unqlite_lib_version, SyMutex,
+    SyMutexMethods,
+};
+use crate::vars::{
+    SXERR_BUSY, SXRET_OK, UNQLITE_LIB_CONFIG_PAGE_SIZE, UNQLITE_LIB_CONFIG_THREAD_LEVEL_MULTI,
+    UNQLITE_LIB_CONFIG_THREAD_LEVEL_SINGLE, UNQLITE_LIB_CONFIG_USER_MUTEX,
+};
+use std::ffi::CStr;
+use std::os::raw::c_int;
+use std::ptr;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread::{self, ThreadId};
+
+/// Default page size of the engine.
+const DEFAULT_PAGE_SIZE: u32 = 4096;
+
+/// Threading level of the library, only meaningful with the `enable-threads` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadLevel {
+    /// Database handles must not be shared between threads, no mutex is ever taken.
+    Single,
+    /// Database handles may be shared between threads. This is the default.
+    Multi,
+}
+
+/// What the library was initialized with, see [`info`](fn.info.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibInfo {
+    /// Page size of new databases
+    pub page_size: u32,
+    /// Threading level
+    pub thread_level: ThreadLevel,
+    /// Whether a `LibMutex` replaced the default mutex implementation
+    pub user_mutex: bool,
+    /// Whether the library was compiled with threading support
+    pub threadsafe: bool,
+    /// Version of the bundled UnQLite
+    pub version: String,
+}
+
+/// A mutex provided to the library, see [`LibConfig::mutex`](struct.LibConfig.html#method.mutex).
+pub trait LibMutex: Send + Sync + 'static {
+    /// Create a mutex. A `recursive` mutex may be locked again by the thread holding it, and
+    /// must then be unlocked as many times.
+    fn new(recursive: bool) -> Self
+    where
+        Self: Sized;
+
+    /// Block until the mutex is acquired.
+    fn lock(&self);
+
+    /// Acquire the mutex if it is free, returns whether it was acquired.
+    fn try_lock(&self) -> bool;
+
+    /// Release the mutex, which is held by the current thread.
+    fn unlock(&self);
+}
+
+/// A recursive mutex on top of `std::sync`, usable for every kind of mutex the library asks for.
+#[derive(Debug, Default)]
+pub struct StdMutex {
+    /// Owner thread and lock count
+    state: Mutex<Option<(ThreadId, usize)>>,
+    released: Condvar,
+}
+
+impl LibMutex for StdMutex {
+    fn new(_recursive: bool) -> Self {
+        StdMutex::default()
+    }
+
+    fn lock(&self) {
+        let me = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match *state {
+                None => {
+                    *state = Some((me, 1));
+                    return;
+                }
+                Some((owner, ref mut count)) if owner == me => {
+                    *count += 1;
+                    return;
+                }
+                Some(_) => state = self.released.wait(state).unwrap(),
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let me = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            None => {
+                *state = Some((me, 1));
+                true
+            }
+            Some((owner, ref mut count)) if owner == me => {
+                *count += 1;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((_, ref mut count)) = *state {
+            *count -= 1;
+            if *count == 0 {
+                *state = None;
+                self.released.notify_one();
+            }
+        }
+    }
+}
+
+/// Library-wide settings, applied once for the whole process.
+#[derive(Clone, Debug, Default)]
+pub struct LibConfig {
+    page_size: Option<u32>,
+    thread_level: Option<ThreadLevel>,
+    mutex: Option<&'static SyMutexMethods>,
+}
+
+impl LibConfig {
+    /// Keep every setting to its default.
+    pub fn new() -> Self {
+        LibConfig::default()
+    }
+
+    /// Page size of new databases, a power of two between 512 and 65536.
+    pub fn page_size(&mut self, page_size: u32) -> &mut Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Threading level of the library.
+    pub fn thread_level(&mut self, thread_level: ThreadLevel) -> &mut Self {
+        self.thread_level = Some(thread_level);
+        self
+    }
+
+    /// Use `M` for every mutex the library creates.
+    ///
+    /// ```no_run
+    /// # extern crate unqlite;
+    /// # use unqlite::lib_config::{LibConfig, StdMutex};
+    /// # fn main() {
+    /// LibConfig::new().mutex::<StdMutex>().init().unwrap();
+    /// # }
+    /// ```
+    pub fn mutex<M: LibMutex>(&mut self) -> &mut Self {
+        self.mutex = Some(Box::leak(Box::new(SyMutexMethods {
+            xGlobalInit: Some(mutex_global_init),
+            xGlobalRelease: Some(mutex_global_release),
+            xNew: Some(mutex_new::<M>),
+            xRelease: Some(mutex_release::<M>),
+            xEnter: Some(mutex_enter::<M>),
+            xTryEnter: Some(mutex_try_enter::<M>),
+            xLeave: Some(mutex_leave::<M>),
+        })));
+        self
+    }
+
+    /// Apply the settings and initialize the library.
+    ///
+    /// This must be called at most once, before the first database is opened. Otherwise the
+    /// library is already initialized and `ErrorKind::LOCKED` is returned.
+    pub fn init(&self) -> Result<()> {
+        if INFO.get().is_some() {
+            return Err(ErrorKind::LOCKED.into());
+        }
+        if let Some(page_size) = self.page_size {
+            if !page_size.is_power_of_two() || !(512..=65536).contains(&page_size) {
+                return Err(ErrorKind::INVALID.into());
+            }
+            unsafe { unqlite_lib_config(UNQLITE_LIB_CONFIG_PAGE_SIZE, page_size as c_int) }
+                .wrap()?;
+        }
+        if let Some(methods) = self.mutex {
+            unsafe { unqlite_lib_config(UNQLITE_LIB_CONFIG_USER_MUTEX, methods) }.wrap()?;
+        }
+        match self.thread_level {
+            Some(ThreadLevel::Single) => {
+                unsafe { unqlite_lib_config(UNQLITE_LIB_CONFIG_THREAD_LEVEL_SINGLE) }.wrap()?
+            }
+            Some(ThreadLevel::Multi) => {
+                unsafe { unqlite_lib_config(UNQLITE_LIB_CONFIG_THREAD_LEVEL_MULTI) }.wrap()?
+            }
+            None => {}
+        }
+        unsafe { unqlite_lib_init() }.wrap()?;
+
+        let mut info = default_info();
+        info.page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        info.thread_level = self.thread_level.unwrap_or(info.thread_level);
+        info.user_mutex = self.mutex.is_some();
+        INFO.set(info).map_err(|_| ErrorKind::LOCKED.into())
+    }
+}
+
+/// What the library was initialized with.
+///
+/// Without a call to [`LibConfig::init`](struct.LibConfig.html#method.init), this reports the
+/// library defaults.
+pub fn info() -> LibInfo {
+    INFO.get().cloned().unwrap_or_else(default_info)
+}
+
+fn default_info() -> LibInfo {
+    let threadsafe = unsafe { unqlite_lib_is_threadsafe() } != 0;
+    LibInfo {
+        page_size: DEFAULT_PAGE_SIZE,
+        thread_level: if threadsafe {
+            ThreadLevel::Multi
+        } else {
+            ThreadLevel::Single
+        },
+        user_mutex: false,
+        threadsafe,
+        version: unsafe { CStr::from_ptr(unqlite_lib_version()) }
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+static INFO: OnceLock<LibInfo> = OnceLock::new();
+
+/// `SXMUTEX_TYPE_*` from the engine, static mutexes come after the recursive one.
+const MUTEX_TYPE_RECURSIVE: c_int = 2;
+const MUTEX_TYPE_STATIC_1: c_int = 3;
+const MUTEX_STATIC_COUNT: usize = 6;
+
+/// Static mutexes are shared and never released, stored as addresses of leaked `M`s.
+static STATIC_MUTEXES: OnceLock<[usize; MUTEX_STATIC_COUNT]> = OnceLock::new();
+
+unsafe extern "C" fn mutex_global_init() -> c_int {
+    SXRET_OK
+}
+
+unsafe extern "C" fn mutex_global_release() {}
+
+unsafe extern "C" fn mutex_new<M: LibMutex>(kind: c_int) -> *mut SyMutex {
+    if kind >= MUTEX_TYPE_STATIC_1 {
+        let index = (kind - MUTEX_TYPE_STATIC_1) as usize;
+        if index >= MUTEX_STATIC_COUNT {
+            return ptr::null_mut();
+        }
+        let mutexes = STATIC_MUTEXES.get_or_init(|| {
+            let mut mutexes = [0; MUTEX_STATIC_COUNT];
+            for mutex in mutexes.iter_mut() {
+                *mutex = Box::into_raw(Box::new(M::new(false))) as usize;
+            }
+            mutexes
+        });
+        return mutexes[index] as *mut SyMutex;
+    }
+    Box::into_raw(Box::new(M::new(kind == MUTEX_TYPE_RECURSIVE))) as *mut SyMutex
+}
+
+unsafe extern "C" fn mutex_release<M: LibMutex>(raw: *mut SyMutex) {
+    let is_static = STATIC_MUTEXES
+        .get()
+        .is_some_and(|mutexes| mutexes.contains(&(raw as usize)));
+    if !raw.is_null() && !is_static {
+        drop(Box::from_raw(raw as *mut M));
+    }
+}
+
+unsafe extern "C" fn mutex_enter<M: LibMutex>(raw: *mut SyMutex) {
+    (*(raw as *const M)).lock()
+}
+
+unsafe extern "C" fn mutex_try_enter<M: LibMutex>(raw: *mut SyMutex) -> c_int {
+    if (*(raw as *const M)).try_lock() {
+        SXRET_OK
+    } else {
+        SXERR_BUSY
+    }
+}
+
+unsafe extern "C" fn mutex_leave<M: LibMutex>(raw: *mut SyMutex) {
+    (*(raw as *const M)).unlock()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LibMutex, StdMutex};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn std_mutex() {
+        let mutex = Arc::new(StdMutex::new(true));
+        mutex.lock();
+        assert!(mutex.try_lock());
+
+        let other = mutex.clone();
+        let locked = thread::spawn(move || other.try_lock()).join().unwrap();
+        assert!(!locked);
+
+        mutex.unlock();
+        mutex.unlock();
+        let other = mutex.clone();
+        let locked = thread::spawn(move || {
+            let locked = other.try_lock();
+            other.unlock();
+            locked
+        })
+        .join()
+        .unwrap();
+        assert!(locked);
+    }
+}
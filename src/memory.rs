@@ -0,0 +1,359 @@
+//! Memory allocation of the UnQLite engine.
+//!
+//! By default the engine allocates through `malloc`. [`Memory`](struct.Memory.html) routes every
+//! allocation made by UnQLite through a Rust `GlobalAlloc` instead, which allows to account for
+//! the memory used by the engine, to cap it and to react when it runs out of memory:
+//!
+//! ```no_run
+//! # extern crate unqlite;
+//! #
+//! use unqlite::memory::{self, Memory};
+//! use unqlite::{UnQLite, KV};
+//!
+//! # fn main() {
+//! // Must happen before the first database is opened.
+//! Memory::new()
+//!     .limit(64 << 20)
+//!     .on_oom(|oom| {
+//!         eprintln!("UnQLite failed to allocate {} bytes", oom.requested);
+//!         false
+//!     })
+//!     .install()
+//!     .unwrap();
+//!
+//! let unqlite = UnQLite::create_in_memory();
+//! unqlite.kv_store("key", "value").unwrap();
+//! println!("UnQLite uses {} bytes", memory::stats().current);
+//! # }
+//! ```
+//!
+//! Once the cap is reached, the failing operation reports `ErrorKind::NOMEM`.
+
+use crate::error::{ErrorKind, Result, Wrap};
+use crate::ffi::{unqlite_lib_config, SyMemMethods};
+use crate::vars::{
+    SXERR_MEM, SXERR_RETRY, UNQLITE_LIB_CONFIG_MEM_ERR_CALLBACK, UNQLITE_LIB_CONFIG_USER_MALLOC,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Memory usage of the engine, see [`stats`](fn.stats.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes currently allocated by UnQLite
+    pub current: usize,
+    /// Highest value of `current` since installed or since the last `reset_peak`
+    pub peak: usize,
+    /// The hard cap, if any
+    pub limit: Option<usize>,
+}
+
+/// An allocation failure handed to the `on_oom` hook.
+#[derive(Clone, Copy, Debug)]
+pub struct Oom {
+    /// Size of the failed allocation
+    pub requested: usize,
+    /// Memory usage at the time of the failure
+    pub stats: MemoryStats,
+}
+
+type OomHook = Arc<dyn Fn(&Oom) -> bool + Send + Sync>;
+
+/// Allocator settings of the engine, installed once for the whole process.
+#[derive(Clone)]
+pub struct Memory {
+    allocator: &'static (dyn GlobalAlloc + Sync),
+    limit: Option<usize>,
+    on_oom: Option<OomHook>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new()
+    }
+}
+
+impl Memory {
+    /// Allocate through the `System` allocator, without cap nor hook.
+    pub fn new() -> Self {
+        Memory {
+            allocator: &System,
+            limit: None,
+            on_oom: None,
+        }
+    }
+
+    /// Allocate through `allocator`, e.g. the program's `#[global_allocator]`.
+    pub fn allocator(&mut self, allocator: &'static (dyn GlobalAlloc + Sync)) -> &mut Self {
+        self.allocator = allocator;
+        self
+    }
+
+    /// Refuse allocations that would bring the engine above `bytes`.
+    ///
+    /// The cap can be changed later with [`set_limit`](fn.set_limit.html).
+    pub fn limit(&mut self, bytes: usize) -> &mut Self {
+        self.limit = Some(bytes);
+        self
+    }
+
+    /// Call `hook` when an allocation fails.
+    ///
+    /// Return `true` to retry the allocation, e.g. after releasing memory or raising the cap,
+    /// `false` to let the operation fail with `ErrorKind::NOMEM`. The engine gives up after a
+    /// few retries.
+    pub fn on_oom<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&Oom) -> bool + Send + Sync + 'static,
+    {
+        self.on_oom = Some(Arc::new(hook));
+        self
+    }
+
+    /// Install these settings for every database of the process.
+    ///
+    /// This must be called once, before the first database is opened. Otherwise the library
+    /// is already initialized and `ErrorKind::LOCKED` is returned. Only the first call may
+    /// succeed, the following ones, including after a failure, return `ErrorKind::LOCKED`.
+    pub fn install(&self) -> Result<()> {
+        // The hooks read the state as soon as they are registered, and only the first install
+        // may register them.
+        let state = State {
+            allocator: self.allocator,
+            on_oom: self.on_oom.clone(),
+        };
+        STATE.set(state).map_err(|_| ErrorKind::LOCKED)?;
+        set_limit(self.limit);
+        // Both calls only fail once the library is initialized: if the callback is registered,
+        // so is the allocator, and the allocator is never left without its callback.
+        if self.on_oom.is_some() {
+            let handler: unsafe extern "C" fn(*mut c_void) -> c_int = mem_error;
+            unsafe {
+                unqlite_lib_config(
+                    UNQLITE_LIB_CONFIG_MEM_ERR_CALLBACK,
+                    handler,
+                    ptr::null_mut::<c_void>(),
+                )
+            }
+            .wrap()?;
+        }
+        // The engine keeps the pointer forever.
+        let methods: &'static SyMemMethods = Box::leak(Box::new(SyMemMethods {
+            xAlloc: Some(mem_alloc),
+            xRealloc: Some(mem_realloc),
+            xFree: Some(mem_free),
+            xChunkSize: Some(mem_chunk_size),
+            xInit: None,
+            xRelease: None,
+            pUserData: ptr::null_mut(),
+        }));
+        unsafe { unqlite_lib_config(UNQLITE_LIB_CONFIG_USER_MALLOC, methods) }.wrap()
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Memory")
+            .field("limit", &self.limit)
+            .field("on_oom", &self.on_oom.is_some())
+            .finish()
+    }
+}
+
+/// Memory usage of the engine.
+///
+/// Only allocations made after [`Memory::install`](struct.Memory.html#method.install) are
+/// accounted for.
+pub fn stats() -> MemoryStats {
+    MemoryStats {
+        current: CURRENT.load(Ordering::Relaxed),
+        peak: PEAK.load(Ordering::Relaxed),
+        limit: match LIMIT.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            limit => Some(limit),
+        },
+    }
+}
+
+/// Change the hard cap, `None` removes it.
+///
+/// Lowering the cap below the current usage does not release anything, it only makes further
+/// allocations fail.
+pub fn set_limit(limit: Option<usize>) {
+    LIMIT.store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Restart peak tracking from the current usage.
+pub fn reset_peak() {
+    PEAK.store(CURRENT.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+struct State {
+    allocator: &'static (dyn GlobalAlloc + Sync),
+    on_oom: Option<OomHook>,
+}
+
+static STATE: OnceLock<State> = OnceLock::new();
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+thread_local! {
+    /// Size of the last failed allocation of this thread, for the `on_oom` hook.
+    static FAILED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Every block starts with its size, so `xFree` can rebuild the layout.
+const HEADER: usize = 16;
+
+fn layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(HEADER)?, HEADER).ok()
+}
+
+/// Account for `size` more bytes, unless that would go past the cap.
+fn reserve(size: usize) -> bool {
+    let limit = LIMIT.load(Ordering::Relaxed);
+    let reserved = CURRENT.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+        current.checked_add(size).filter(|&total| total <= limit)
+    });
+    match reserved {
+        Ok(previous) => {
+            PEAK.fetch_max(previous + size, Ordering::Relaxed);
+            true
+        }
+        Err(_) => {
+            FAILED.with(|failed| failed.set(size));
+            false
+        }
+    }
+}
+
+fn release(size: usize) {
+    CURRENT.fetch_sub(size, Ordering::Relaxed);
+}
+
+fn allocator() -> &'static (dyn GlobalAlloc + Sync) {
+    STATE.get().map_or(&System, |state| state.allocator)
+}
+
+unsafe fn header(block: *mut c_void) -> *mut u8 {
+    (block as *mut u8).sub(HEADER)
+}
+
+unsafe fn block_size(block: *mut c_void) -> usize {
+    ptr::read(header(block) as *const usize)
+}
+
+/// Store `size` in the header at `raw` and return the user block.
+unsafe fn init_block(raw: *mut u8, size: usize) -> *mut c_void {
+    ptr::write(raw as *mut usize, size);
+    raw.add(HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn mem_alloc(size: c_uint) -> *mut c_void {
+    let size = size as usize;
+    let layout = match layout(size) {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    if !reserve(size) {
+        return ptr::null_mut();
+    }
+    let raw = allocator().alloc(layout);
+    if raw.is_null() {
+        release(size);
+        FAILED.with(|failed| failed.set(size));
+        return ptr::null_mut();
+    }
+    init_block(raw, size)
+}
+
+unsafe extern "C" fn mem_realloc(block: *mut c_void, size: c_uint) -> *mut c_void {
+    if block.is_null() {
+        return mem_alloc(size);
+    }
+    let (old, size) = (block_size(block), size as usize);
+    let new_layout = match layout(size) {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    if size > old && !reserve(size - old) {
+        return ptr::null_mut();
+    }
+    let raw = allocator().realloc(header(block), layout(old).unwrap(), new_layout.size());
+    if raw.is_null() {
+        if size > old {
+            release(size - old);
+            FAILED.with(|failed| failed.set(size));
+        }
+        return ptr::null_mut();
+    }
+    if size < old {
+        release(old - size);
+    }
+    init_block(raw, size)
+}
+
+unsafe extern "C" fn mem_free(block: *mut c_void) {
+    if block.is_null() {
+        return;
+    }
+    let size = block_size(block);
+    allocator().dealloc(header(block), layout(size).unwrap());
+    release(size);
+}
+
+unsafe extern "C" fn mem_chunk_size(block: *mut c_void) -> c_uint {
+    if block.is_null() {
+        0
+    } else {
+        block_size(block) as c_uint
+    }
+}
+
+unsafe extern "C" fn mem_error(_user_data: *mut c_void) -> c_int {
+    let hook = match STATE.get().and_then(|state| state.on_oom.as_ref()) {
+        Some(hook) => hook,
+        None => return SXERR_MEM,
+    };
+    let oom = Oom {
+        requested: FAILED.with(Cell::get),
+        stats: stats(),
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| hook(&oom))) {
+        Ok(true) => SXERR_RETRY,
+        _ => SXERR_MEM,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounting() {
+        unsafe {
+            let before = stats().current;
+            let block = mem_alloc(100);
+            assert!(!block.is_null());
+            assert_eq!(mem_chunk_size(block), 100);
+            assert_eq!(stats().current, before + 100);
+
+            let block = mem_realloc(block, 300);
+            assert_eq!(mem_chunk_size(block), 300);
+            assert_eq!(stats().current, before + 300);
+            assert!(stats().peak >= before + 300);
+
+            let block = mem_realloc(block, 50);
+            assert_eq!(stats().current, before + 50);
+            mem_free(block);
+            assert_eq!(stats().current, before);
+        }
+    }
+}
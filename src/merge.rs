@@ -0,0 +1,265 @@
+//! Merge operators.
+//!
+//! A [`MergeOperator`](trait.MergeOperator.html) combines the value of a record with an operand,
+//! so that [`KV::kv_merge`](../trait.KV.html#tymethod.kv_merge) updates aggregates atomically
+//! instead of fetching and storing them in application code. Operators are registered on a
+//! handle under their [`name`](trait.MergeOperator.html#tymethod.name) with
+//! [`UnQLite::register_merge_operator`](../struct.UnQLite.html#method.register_merge_operator),
+//! and each merge names the operator to use.
+//!
+//! Bundled operators:
+//!
+//! Operator | Value
+//! --- | ---
+//! [`SumI64`](struct.SumI64.html) | Sum of 64-bit little-endian integers, like `kv_incr`
+//! [`MaxI64`](struct.MaxI64.html) | Maximum of 64-bit little-endian integers
+//! [`SetUnion`](struct.SetUnion.html) | Union of sets of byte strings
+//! `JsonMerge` | JSON merge patch (RFC 7396), with the `json` feature
+//!
+//! ```
+//! # extern crate unqlite;
+//! #
+//! use unqlite::merge::SumI64;
+//! use unqlite::{UnQLite, KV};
+//!
+//! # #[cfg(feature = "enable-threads")]
+//! # fn main() {
+//! let unqlite = UnQLite::create_in_memory();
+//! unqlite.register_merge_operator(SumI64);
+//! unqlite.kv_merge("total", "sum_i64", 5i64.to_le_bytes()).unwrap();
+//! unqlite.kv_merge("total", "sum_i64", 7i64.to_le_bytes()).unwrap();
+//! assert_eq!(unqlite.kv_fetch("total").unwrap(), 12i64.to_le_bytes());
+//! # }
+//! # #[cfg(not(feature = "enable-threads"))]
+//! # fn main() { }
+//! ```
+
+use crate::error::{ErrorKind, Result};
+use crate::{CounterError, UnQLite};
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::sync::{Arc, PoisonError};
+
+/// Combination of a record with an operand.
+pub trait MergeOperator: Send + Sync + 'static {
+    /// Name of the operator, under which it is registered.
+    fn name(&self) -> &str;
+
+    /// Merge `operand` into `existing`, which is `None` if there is no such record, and return
+    /// the new value.
+    fn merge(&self, existing: Option<&[u8]>, operand: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl UnQLite {
+    /// Register `operator` under its name for [`KV::kv_merge`](trait.KV.html#tymethod.kv_merge)
+    /// on this handle, replacing any operator of the same name.
+    pub fn register_merge_operator<M: MergeOperator>(&self, operator: M) {
+        let name = operator.name().to_owned();
+        self.merge
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, Arc::new(operator));
+    }
+
+    /// The operator registered under `name`, if any.
+    pub fn merge_operator(&self, name: &str) -> Option<Arc<dyn MergeOperator>> {
+        self.merge
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    }
+}
+
+fn counter(value: &[u8]) -> Result<i64> {
+    value
+        .try_into()
+        .map(i64::from_le_bytes)
+        .map_err(|_| CounterError::NotACounter { len: value.len() }.into())
+}
+
+/// Sum of 64-bit little-endian integers, failing with a `CounterError` on overflow.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SumI64;
+
+impl MergeOperator for SumI64 {
+    fn name(&self) -> &str {
+        "sum_i64"
+    }
+
+    fn merge(&self, existing: Option<&[u8]>, operand: &[u8]) -> Result<Vec<u8>> {
+        let existing = existing.map_or(Ok(0), counter)?;
+        let sum = existing
+            .checked_add(counter(operand)?)
+            .ok_or(CounterError::Overflow)?;
+        Ok(sum.to_le_bytes().to_vec())
+    }
+}
+
+/// Maximum of 64-bit little-endian integers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaxI64;
+
+impl MergeOperator for MaxI64 {
+    fn name(&self) -> &str {
+        "max_i64"
+    }
+
+    fn merge(&self, existing: Option<&[u8]>, operand: &[u8]) -> Result<Vec<u8>> {
+        let operand = counter(operand)?;
+        let max = match existing {
+            Some(existing) => counter(existing)?.max(operand),
+            None => operand,
+        };
+        Ok(max.to_le_bytes().to_vec())
+    }
+}
+
+/// Union of sets of byte strings.
+///
+/// A set is stored as its sorted members, each one preceded by its length as a 32-bit
+/// little-endian integer. Use [`encode`](#method.encode) to build operands and
+/// [`decode`](#method.decode) to read values. A malformed set fails with `ErrorKind::INVALID`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetUnion;
+
+impl SetUnion {
+    /// Encode a set of members.
+    pub fn encode<I, T>(members: I) -> Vec<u8>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let members: BTreeSet<Vec<u8>> = members
+            .into_iter()
+            .map(|member| member.as_ref().to_vec())
+            .collect();
+        let mut encoded = Vec::new();
+        for member in &members {
+            encoded.extend_from_slice(&(member.len() as u32).to_le_bytes());
+            encoded.extend_from_slice(member);
+        }
+        encoded
+    }
+
+    /// Decode the members of a set.
+    pub fn decode(mut encoded: &[u8]) -> Result<BTreeSet<Vec<u8>>> {
+        let mut members = BTreeSet::new();
+        while !encoded.is_empty() {
+            if encoded.len() < 4 {
+                return Err(ErrorKind::INVALID.into());
+            }
+            let (len, rest) = encoded.split_at(4);
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            if rest.len() < len {
+                return Err(ErrorKind::INVALID.into());
+            }
+            let (member, rest) = rest.split_at(len);
+            members.insert(member.to_vec());
+            encoded = rest;
+        }
+        Ok(members)
+    }
+}
+
+impl MergeOperator for SetUnion {
+    fn name(&self) -> &str {
+        "set_union"
+    }
+
+    fn merge(&self, existing: Option<&[u8]>, operand: &[u8]) -> Result<Vec<u8>> {
+        let mut members = SetUnion::decode(operand)?;
+        if let Some(existing) = existing {
+            members.extend(SetUnion::decode(existing)?);
+        }
+        Ok(SetUnion::encode(members))
+    }
+}
+
+/// JSON merge patch, as of RFC 7396: the operand is a patch applied to the JSON value.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonMerge;
+
+#[cfg(feature = "json")]
+impl MergeOperator for JsonMerge {
+    fn name(&self) -> &str {
+        "json_merge"
+    }
+
+    fn merge(&self, existing: Option<&[u8]>, operand: &[u8]) -> Result<Vec<u8>> {
+        let mut value = match existing {
+            Some(existing) => serde_json::from_slice(existing)?,
+            None => serde_json::Value::Null,
+        };
+        json_merge_patch(&mut value, serde_json::from_slice(operand)?);
+        Ok(serde_json::to_vec(&value)?)
+    }
+}
+
+#[cfg(feature = "json")]
+fn json_merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    use serde_json::Value;
+
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Default::default());
+            }
+            let target = target.as_object_mut().unwrap();
+            for (name, value) in patch {
+                if value.is_null() {
+                    target.remove(&name);
+                } else {
+                    json_merge_patch(target.entry(name).or_insert(Value::Null), value);
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MaxI64, MergeOperator, SetUnion, SumI64};
+
+    #[test]
+    fn operators() {
+        let one = 1i64.to_le_bytes();
+        let two = SumI64.merge(Some(&one), &one).unwrap();
+        assert_eq!(two, 2i64.to_le_bytes());
+        assert!(SumI64.merge(Some(b"abc"), &one).is_err());
+        assert!(SumI64.merge(Some(&i64::MAX.to_le_bytes()), &one).is_err());
+
+        assert_eq!(MaxI64.merge(Some(&two), &one).unwrap(), two);
+        assert_eq!(MaxI64.merge(None, &one).unwrap(), one);
+
+        let set = SetUnion
+            .merge(
+                Some(&SetUnion::encode(["b", "a"])),
+                &SetUnion::encode(["c", "a"]),
+            )
+            .unwrap();
+        let members: Vec<_> = SetUnion::decode(&set).unwrap().into_iter().collect();
+        assert_eq!(members, [b"a", b"b", b"c"]);
+        assert!(SetUnion.merge(None, &[1, 0]).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_merge() {
+        use super::JsonMerge;
+
+        let value = JsonMerge
+            .merge(
+                Some(br#"{"a":1,"b":{"c":2,"d":3}}"#),
+                br#"{"b":{"c":null,"e":4},"f":5}"#,
+            )
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&value).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"a": 1, "b": {"d": 3, "e": 4}, "f": 5})
+        );
+    }
+}
@@ -1,30 +1,267 @@
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum OpenMode {
-    ReadOnly,
-    // ReadWrite,
-    Create,
-    // Exclusive,
-    TempDB,
-    // NoMutex,
-    // OmitJournaling,
-    // InMemory,
-    MMap,
+use crate::busy::BusyPolicy;
+use crate::error::{Error, Result};
+use crate::vars::{
+    UNQLITE_INVALID, UNQLITE_OPEN_CREATE, UNQLITE_OPEN_EXCLUSIVE, UNQLITE_OPEN_IN_MEMORY,
+    UNQLITE_OPEN_MMAP, UNQLITE_OPEN_NOMUTEX, UNQLITE_OPEN_OMIT_JOURNALING, UNQLITE_OPEN_READONLY,
+    UNQLITE_OPEN_READWRITE, UNQLITE_OPEN_TEMP_DB,
+};
+use crate::UnQLite;
+
+/// Options and flags which can be used to configure how a database is opened.
+///
+/// This builder exposes every `UNQLITE_OPEN_*` control flag accepted by
+/// [`unqlite_open`][open]. Unlike the `UnQLite` constructors, `open` never panics: a missing
+/// file, a permission problem or an invalid combination of flags is reported as an `Error`.
+///
+/// ```
+/// # extern crate unqlite;
+/// #
+/// use unqlite::{OpenOptions, KV};
+///
+/// # #[cfg(feature = "enable-threads")]
+/// # fn main() {
+/// let unqlite = OpenOptions::new()
+///     .in_memory(true)
+///     .omit_journaling(true)
+///     .open(":mem:")
+///     .expect("open in-memory database");
+/// unqlite.kv_store("key", "value").unwrap();
+/// # }
+/// # #[cfg(not(feature = "enable-threads"))]
+/// # fn main() { }
+/// ```
+///
+/// [open]: https://unqlite.org/c_api/unqlite_open.html
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    exclusive: bool,
+    temp: bool,
+    no_mutex: bool,
+    omit_journaling: bool,
+    in_memory: bool,
+    mmap: bool,
+    busy: BusyPolicy,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions::new()
+    }
 }
 
-pub use self::OpenMode::*;
-
-impl Into<u32> for OpenMode {
-    fn into(self) -> u32 {
-        match self {
-            ReadOnly => crate::vars::UNQLITE_OPEN_READONLY,
-            // ReadWrite => vars::UNQLITE_OPEN_READWRITE,
-            Create => crate::vars::UNQLITE_OPEN_CREATE,
-            // Exclusive =>vars::UNQLITE_OPEN_EXCLUSIVE,
-            TempDB => crate::vars::UNQLITE_OPEN_TEMP_DB,
-            // NoMutex =>vars::UNQLITE_OPEN_NOMUTEX,
-            // OmitJournaling =>vars::UNQLITE_OPEN_OMIT_JOURNALING,
-            // InMemory =>vars::UNQLITE_OPEN_IN_MEMORY,
-            MMap => crate::vars::UNQLITE_OPEN_MMAP | crate::vars::UNQLITE_OPEN_READONLY,
+impl OpenOptions {
+    /// Creates a blank new set of options ready for configuration.
+    ///
+    /// Only `read` is set by default, so the database is opened in read-only mode.
+    pub fn new() -> Self {
+        OpenOptions {
+            read: true,
+            write: false,
+            create: false,
+            exclusive: false,
+            temp: false,
+            no_mutex: false,
+            omit_journaling: false,
+            in_memory: false,
+            mmap: false,
+            busy: BusyPolicy::default(),
         }
     }
+
+    /// Sets the option for read access (`UNQLITE_OPEN_READONLY`).
+    ///
+    /// Read access is implied by `write` and `create`.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Sets the option for read-write access (`UNQLITE_OPEN_READWRITE`).
+    ///
+    /// The database file must already exist unless `create` is also set.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Create the database file if it does not exist (`UNQLITE_OPEN_CREATE`).
+    ///
+    /// This implies read-write access.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Fail if the database file already exists (`UNQLITE_OPEN_EXCLUSIVE`).
+    ///
+    /// Only meaningful together with `create`.
+    pub fn exclusive(&mut self, exclusive: bool) -> &mut Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Create a private, temporary on-disk database which is deleted as soon as the handle
+    /// is closed (`UNQLITE_OPEN_TEMP_DB`).
+    ///
+    /// This implies `create`; the file name given to `open` is ignored.
+    pub fn temp(&mut self, temp: bool) -> &mut Self {
+        self.temp = temp;
+        self
+    }
+
+    /// Disable the private recursive mutex associated with the handle
+    /// (`UNQLITE_OPEN_NOMUTEX`).
+    ///
+    /// The handle must then not be shared between threads.
+    pub fn no_mutex(&mut self, no_mutex: bool) -> &mut Self {
+        self.no_mutex = no_mutex;
+        self
+    }
+
+    /// Disable journaling for this database (`UNQLITE_OPEN_OMIT_JOURNALING`).
+    ///
+    /// Transactions are no longer atomic and a crash in the middle of a commit may corrupt
+    /// the database file.
+    pub fn omit_journaling(&mut self, omit_journaling: bool) -> &mut Self {
+        self.omit_journaling = omit_journaling;
+        self
+    }
+
+    /// Create a private, in-memory database (`UNQLITE_OPEN_IN_MEMORY`).
+    ///
+    /// This implies `create`; the file name given to `open` is ignored.
+    pub fn in_memory(&mut self, in_memory: bool) -> &mut Self {
+        self.in_memory = in_memory;
+        self
+    }
+
+    /// Obtain a read-only memory view of the whole database (`UNQLITE_OPEN_MMAP`).
+    ///
+    /// This implies read-only access, so it cannot be combined with `write` or `create`.
+    pub fn mmap(&mut self, mmap: bool) -> &mut Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Sets the policy applied when the database is locked or busy.
+    ///
+    /// It is used while opening the database and kept by the returned handle, see
+    /// [`UnQLite::busy_policy`](struct.UnQLite.html#method.busy_policy).
+    pub fn busy_policy(&mut self, policy: BusyPolicy) -> &mut Self {
+        self.busy = policy;
+        self
+    }
+
+    /// Opens the database at `filename` with the options specified by `self`.
+    ///
+    /// ## C
+    ///
+    /// ```c
+    /// unqlite *pDb;
+    /// rc = unqlite_open(&pDb, "test.db", UNQLITE_OPEN_READWRITE | UNQLITE_OPEN_NOMUTEX);
+    /// ```
+    pub fn open<P: AsRef<str>>(&self, filename: P) -> Result<UnQLite> {
+        UnQLite::open(filename, self.mode()?, self.busy.clone())
+    }
+
+    /// Translate the options into `UNQLITE_OPEN_*` flags.
+    fn mode(&self) -> Result<u32> {
+        let create = self.create || self.temp || self.in_memory;
+        let writable = self.write || create;
+        if !(self.read || writable) {
+            return Err(Error::from_code(UNQLITE_INVALID));
+        }
+        if self.mmap && writable {
+            return Err(Error::from_code(UNQLITE_INVALID));
+        }
+
+        let mut mode = if create {
+            UNQLITE_OPEN_CREATE
+        } else if self.write {
+            UNQLITE_OPEN_READWRITE
+        } else {
+            UNQLITE_OPEN_READONLY
+        };
+        let flags = [
+            (self.exclusive, UNQLITE_OPEN_EXCLUSIVE),
+            (self.temp, UNQLITE_OPEN_TEMP_DB),
+            (self.no_mutex, UNQLITE_OPEN_NOMUTEX),
+            (self.omit_journaling, UNQLITE_OPEN_OMIT_JOURNALING),
+            (self.in_memory, UNQLITE_OPEN_IN_MEMORY),
+            (self.mmap, UNQLITE_OPEN_MMAP),
+        ];
+        for &(enabled, flag) in flags.iter() {
+            if enabled {
+                mode |= flag;
+            }
+        }
+        Ok(mode)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "enable-threads")]
+mod tests {
+    use super::OpenOptions;
+    use crate::vars::*;
+    use crate::KV;
+    use tempfile::tempdir;
+
+    #[test]
+    fn mode() {
+        assert_eq!(OpenOptions::new().mode().unwrap(), UNQLITE_OPEN_READONLY);
+        assert_eq!(
+            OpenOptions::new().write(true).mode().unwrap(),
+            UNQLITE_OPEN_READWRITE
+        );
+        assert_eq!(
+            OpenOptions::new()
+                .create(true)
+                .exclusive(true)
+                .omit_journaling(true)
+                .mode()
+                .unwrap(),
+            UNQLITE_OPEN_CREATE | UNQLITE_OPEN_EXCLUSIVE | UNQLITE_OPEN_OMIT_JOURNALING
+        );
+        assert_eq!(
+            OpenOptions::new().mmap(true).mode().unwrap(),
+            UNQLITE_OPEN_READONLY | UNQLITE_OPEN_MMAP
+        );
+        assert_eq!(
+            OpenOptions::new().temp(true).mode().unwrap(),
+            UNQLITE_OPEN_CREATE | UNQLITE_OPEN_TEMP_DB
+        );
+        assert!(OpenOptions::new().read(false).mode().is_err());
+        assert!(OpenOptions::new().write(true).mmap(true).mode().is_err());
+    }
+
+    #[test]
+    fn open() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("open.db");
+        let path = path.to_str().unwrap();
+
+        assert!(OpenOptions::new().write(true).open(path).is_err());
+
+        let unqlite = OpenOptions::new().create(true).open(path).unwrap();
+        unqlite.kv_store("key", "value").unwrap();
+        drop(unqlite);
+
+        let unqlite = OpenOptions::new().write(true).open(path).unwrap();
+        assert_eq!(unqlite.kv_fetch("key").unwrap(), b"value");
+    }
+
+    #[test]
+    fn in_memory() {
+        let unqlite = OpenOptions::new()
+            .in_memory(true)
+            .no_mutex(true)
+            .open(":mem:")
+            .unwrap();
+        unqlite.kv_store("key", "value").unwrap();
+        assert!(unqlite.kv_contains("key"));
+    }
 }
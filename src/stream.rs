@@ -0,0 +1,393 @@
+use crate::error::{Error, ErrorKind, Result};
+use crate::{UnQLite, KV};
+use std::cmp;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::ControlFlow;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Size of the chunks moved between the database and a reader or writer.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Prefix of the staging records of writers, never a bucket prefix as `0xff` is not valid UTF-8.
+const STAGING_PREFIX: &[u8] = b"\0\xffstream\0";
+
+/// Streams a record out of the database, see [`KV::kv_reader`](trait.KV.html#tymethod.kv_reader).
+///
+/// The value is read in chunks through the fetch callback. The engine cannot resume a fetch, so
+/// each chunk walks the record from its start again. To keep reading linear in the size of the
+/// record, chunks start at 64 KiB and grow to a quarter of the data read so far: the record is
+/// walked about five times in total, with at most a fifth of it buffered.
+///
+/// If the record is removed or shortened while being read, reading fails with
+/// `io::ErrorKind::UnexpectedEof`.
+pub struct KvReader<'db> {
+    unqlite: &'db UnQLite,
+    key: Vec<u8>,
+    /// Length of the record when the reader was created
+    len: u64,
+    /// Offset in the record of the end of `buf`
+    pos: u64,
+    buf: Vec<u8>,
+    /// Bytes of `buf` already consumed
+    consumed: usize,
+}
+
+impl<'db> KvReader<'db> {
+    pub(crate) fn new(unqlite: &'db UnQLite, key: &[u8]) -> Result<Self> {
+        let len = unqlite.raw().kv_fetch_length(key)?;
+        Ok(KvReader {
+            unqlite,
+            key: key.to_vec(),
+            len: len as u64,
+            pos: 0,
+            buf: Vec::new(),
+            consumed: 0,
+        })
+    }
+
+    /// Length of the record when the reader was created.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the record was empty when the reader was created.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Fetch the chunk following `pos` into `buf`.
+    fn fill(&mut self) -> io::Result<()> {
+        let buf = &mut self.buf;
+        let mut skip = self.pos as usize;
+        let size = cmp::max(CHUNK_SIZE, skip / 4);
+        buf.clear();
+        self.consumed = 0;
+        let result = self.unqlite.raw().kv_fetch_callback(&self.key, |data| {
+            if skip >= data.len() {
+                skip -= data.len();
+                return ControlFlow::Continue(());
+            }
+            let data = &data[skip..];
+            skip = 0;
+            let take = cmp::min(size - buf.len(), data.len());
+            buf.extend_from_slice(&data[..take]);
+            if buf.len() == size {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        match result {
+            Err(ref err) if err.kind() == Some(ErrorKind::NOTFOUND) => {}
+            result => result?,
+        }
+        if self.buf.is_empty() {
+            // The record was removed or shortened in between.
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "record changed while being read",
+            ));
+        }
+        self.pos += self.buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl<'db> BufRead for KvReader<'db> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed >= self.buf.len() && self.pos < self.len {
+            self.fill()?;
+        }
+        Ok(&self.buf[self.consumed..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed = cmp::min(self.consumed + amt, self.buf.len());
+    }
+}
+
+impl<'db> Read for KvReader<'db> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = cmp::min(available.len(), out.len());
+            out[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'db> fmt::Debug for KvReader<'db> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KvReader")
+            .field("key", &self.key)
+            .field("len", &self.len)
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+/// Streams a record into the database, see [`KV::kv_writer`](trait.KV.html#tymethod.kv_writer).
+///
+/// Data is buffered and written in chunks of 64 KiB to a staging record under a reserved key,
+/// which neither cursors nor buckets list. [`finish`](#method.finish) then copies it over the
+/// record and removes it in a single locked update, so the record is replaced at once and other
+/// writers are only held up during that copy. Dropping the writer before `finish` removes the
+/// staging record, leaving the record as it was.
+///
+/// Finishing fails with `ErrorKind::NOTIMPLEMENTED` if the engine is not transactional, see
+/// [`UnQLite::is_transactional`](struct.UnQLite.html#method.is_transactional).
+pub struct KvWriter<'db> {
+    unqlite: &'db UnQLite,
+    key: Vec<u8>,
+    /// Reserved key of the staging record
+    staging: Vec<u8>,
+    buf: Vec<u8>,
+    /// Bytes written to the staging record so far
+    written: u64,
+    /// The staging record was created by a first chunk
+    started: bool,
+    /// The key is reserved, writing fails with `ErrorKind::INVALID`
+    pub(crate) invalid: bool,
+}
+
+impl<'db> KvWriter<'db> {
+    pub(crate) fn new(unqlite: &'db UnQLite, key: &[u8]) -> Self {
+        static STAGING_ID: AtomicU64 = AtomicU64::new(0);
+        let mut staging = STAGING_PREFIX.to_vec();
+        staging.extend_from_slice(&process::id().to_be_bytes());
+        staging.extend_from_slice(&STAGING_ID.fetch_add(1, Ordering::SeqCst).to_be_bytes());
+        KvWriter {
+            unqlite,
+            key: key.to_vec(),
+            staging,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+            started: false,
+            invalid: false,
+        }
+    }
+
+    /// Write the remaining data, replace the record and return its length.
+    ///
+    /// If nothing was written, the record is replaced by an empty one. On failure, the record is
+    /// left as it was.
+    pub fn finish(mut self) -> Result<u64> {
+        self.flush_buf()?;
+        if self.invalid {
+            return Err(ErrorKind::INVALID.into());
+        }
+        self.unqlite.check_transactional()?;
+        let unqlite = self.unqlite;
+        let (key, staging) = (&self.key, &self.staging);
+        let started = self.started;
+        unqlite.locked_update(|| {
+            if !started {
+                return unqlite.kv_store_unlocked(key, &[]);
+            }
+            let mut reader = KvReader::new(unqlite, staging)?;
+            let mut first = true;
+            loop {
+                let chunk = reader.fill_buf().map_err(|err| Error::Other(Box::new(err)))?;
+                let len = chunk.len();
+                if first {
+                    unqlite.kv_store_unlocked(key, chunk)?;
+                    first = false;
+                } else if len > 0 {
+                    unqlite.kv_append_unlocked(key, chunk)?;
+                }
+                if len == 0 {
+                    break;
+                }
+                reader.consume(len);
+            }
+            unqlite.kv_delete_unlocked(staging)
+        })?;
+        self.started = false;
+        Ok(self.written)
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<()> {
+        if self.invalid {
+            return Err(ErrorKind::INVALID.into());
+        }
+        if self.started {
+            self.unqlite.raw().kv_append(&self.staging, data)?;
+        } else {
+            // Flag first, so a failed store still gets cleaned up on drop.
+            self.started = true;
+            self.unqlite.raw().kv_store(&self.staging, data)?;
+        }
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    fn flush_buf(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let buf = std::mem::take(&mut self.buf);
+        let result = self.put(&buf);
+        self.buf = buf;
+        self.buf.clear();
+        result
+    }
+}
+
+impl<'db> Write for KvWriter<'db> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() > CHUNK_SIZE {
+            self.flush_buf()?;
+        }
+        if data.len() >= CHUNK_SIZE {
+            self.put(data)?;
+        } else {
+            self.buf.extend_from_slice(data);
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.flush_buf()?)
+    }
+}
+
+impl<'db> Drop for KvWriter<'db> {
+    fn drop(&mut self) {
+        if self.started {
+            let _ = self.unqlite.raw().kv_delete(&self.staging);
+        }
+    }
+}
+
+impl<'db> fmt::Debug for KvWriter<'db> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KvWriter")
+            .field("key", &self.key)
+            .field("buffered", &self.buf.len())
+            .field("written", &self.written)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "enable-threads")]
+mod tests {
+    use super::{CHUNK_SIZE, STAGING_PREFIX};
+    use crate::{Cursor, Entry, ErrorKind, Transaction, UnQLite, KV};
+    use std::io::{self, BufRead, Read, Write};
+
+    fn blob(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn write_read() {
+        let unqlite = UnQLite::create_temp();
+        unqlite.kv_store("blob", "old value").unwrap();
+
+        let data = blob(3 * CHUNK_SIZE + 17);
+        let mut writer = unqlite.kv_writer("blob");
+        for part in data.chunks(1000) {
+            writer.write_all(part).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), data.len() as u64);
+        assert_eq!(unqlite.kv_fetch("blob").unwrap(), data);
+
+        let mut reader = unqlite.kv_reader("blob").unwrap();
+        assert_eq!(reader.len(), data.len() as u64);
+        let mut read = Vec::new();
+        io::copy(&mut reader, &mut read).unwrap();
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn lines() {
+        let unqlite = UnQLite::create_in_memory();
+        let mut writer = unqlite.kv_writer("lines");
+        writer.write_all(b"first\nsecond\n").unwrap();
+        writer.finish().unwrap();
+
+        let reader = unqlite.kv_reader("lines").unwrap();
+        let lines: Vec<String> = reader.lines().map(|line| line.unwrap()).collect();
+        assert_eq!(lines, ["first", "second"]);
+    }
+
+    #[test]
+    fn empty_and_missing() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("key", "value").unwrap();
+        unqlite.kv_writer("key").finish().unwrap();
+        assert_eq!(unqlite.kv_fetch_length("key").unwrap(), 0);
+
+        let mut buf = Vec::new();
+        let mut reader = unqlite.kv_reader("key").unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_to_end(&mut buf).unwrap(), 0);
+
+        let err = unqlite.kv_reader("missing").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::NOTFOUND));
+    }
+
+    #[test]
+    fn drop_discards() {
+        let unqlite = UnQLite::create_temp();
+        unqlite.kv_store("blob", "old value").unwrap();
+        unqlite.commit().unwrap();
+
+        let mut writer = unqlite.kv_writer("blob");
+        writer.write_all(&blob(CHUNK_SIZE + 1)).unwrap();
+        drop(writer);
+        assert_eq!(unqlite.kv_fetch("blob").unwrap(), b"old value");
+        assert!(Entry::first(&unqlite, STAGING_PREFIX.to_vec()).is_none());
+    }
+
+    #[test]
+    fn staged_until_finish() {
+        let unqlite = UnQLite::create_temp();
+        unqlite.kv_store("blob", "old value").unwrap();
+
+        let data = blob(2 * CHUNK_SIZE + 1);
+        let mut writer = unqlite.kv_writer("blob");
+        writer.write_all(&data).unwrap();
+        unqlite.kv_store("other", "value").unwrap();
+        assert_eq!(unqlite.kv_fetch("blob").unwrap(), b"old value");
+        assert_eq!(unqlite.iter().count(), 2);
+
+        // Dropping another writer leaves this one and the other changes alone.
+        let mut dropped = unqlite.kv_writer("other");
+        dropped.write_all(&blob(CHUNK_SIZE + 1)).unwrap();
+        drop(dropped);
+        assert_eq!(writer.finish().unwrap(), data.len() as u64);
+        assert_eq!(unqlite.kv_fetch("blob").unwrap(), data);
+        assert_eq!(unqlite.kv_fetch("other").unwrap(), b"value");
+        assert!(Entry::first(&unqlite, STAGING_PREFIX.to_vec()).is_none());
+    }
+
+    #[test]
+    fn reserved_key() {
+        let unqlite = UnQLite::create_in_memory();
+        let mut writer = unqlite.kv_writer("\0key");
+        let err = writer.write_all(&blob(CHUNK_SIZE)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        let err = unqlite.kv_writer("\0key").finish().unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::INVALID));
+    }
+
+    #[test]
+    fn removed_while_reading() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("blob", blob(2 * CHUNK_SIZE)).unwrap();
+
+        let mut reader = unqlite.kv_reader("blob").unwrap();
+        let mut buf = vec![0; CHUNK_SIZE];
+        reader.read_exact(&mut buf).unwrap();
+        unqlite.kv_delete("blob").unwrap();
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
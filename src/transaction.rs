@@ -1,6 +1,7 @@
 use crate::error::{Result, Wrap};
 use crate::ffi::{unqlite_begin, unqlite_commit, unqlite_rollback};
 use crate::UnQLite;
+use std::sync::atomic::Ordering;
 
 /// Manual Transaction Manager
 ///
@@ -14,6 +15,8 @@ pub trait Transaction {
     /// automatically. An automatic transaction is started each time upper-layers or client code
     /// request a store, delete or an append operation.
     ///
+    /// While a transaction begun this way is open, [`ttl::sweep`](ttl/fn.sweep.html) leaves the
+    /// database alone.
     fn begin(&self) -> Result<()>;
 
     /// Commit all changes to the database.
@@ -43,10 +46,16 @@ pub trait Transaction {
 
 impl Transaction for UnQLite {
     fn begin(&self) -> Result<()> {
-        wrap_raw!(self, begin)
+        // Not in the middle of a conditional write or a sweep, which would commit it.
+        self.with_update_lock(|| {
+            wrap_raw!(self, begin)?;
+            self.transaction.store(true, Ordering::Relaxed);
+            Ok(())
+        })
     }
 
     fn commit(&self) -> Result<()> {
+        self.transaction.store(false, Ordering::Relaxed);
         wrap_raw!(self, commit).map_err(|err| {
             let _ = self.rollback();
             err
@@ -54,10 +63,18 @@ impl Transaction for UnQLite {
     }
 
     fn rollback(&self) -> Result<()> {
+        self.transaction.store(false, Ordering::Relaxed);
         wrap_raw!(self, rollback)
     }
 }
 
+impl UnQLite {
+    /// Whether a transaction begun with `Transaction::begin` is open.
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.transaction.load(Ordering::Relaxed)
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "enable-threads")]
 mod tests {
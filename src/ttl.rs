@@ -0,0 +1,392 @@
+//! Expiring records.
+//!
+//! [`KV::kv_store_with_ttl`](../trait.KV.html#tymethod.kv_store_with_ttl) stores a record along
+//! with its expiry time, in a metadata record under a reserved key: `\0`, `0xff`, `ttl`, `\0`
+//! and the key of the record. Expired records are missing for reads and cursors, and removed
+//! when written to. A [`Sweeper`](struct.Sweeper.html) thread, or a call to
+//! [`sweep`](fn.sweep.html), removes them in bulk.
+//!
+//! Overwriting a record with `kv_store` or removing it, through a cursor too, clears its
+//! time-to-live, while `kv_append` and `kv_incr` keep it.
+//!
+//! ```
+//! # extern crate unqlite;
+//! #
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use unqlite::ttl::Sweeper;
+//! use unqlite::{UnQLite, KV};
+//!
+//! # #[cfg(feature = "enable-threads")]
+//! # fn main() {
+//! let unqlite = Arc::new(UnQLite::create_in_memory());
+//! let _sweeper = Sweeper::spawn(unqlite.clone(), Duration::from_secs(60));
+//!
+//! unqlite
+//!     .kv_store_with_ttl("session", "token", Duration::from_secs(3600))
+//!     .unwrap();
+//! assert!(unqlite.kv_ttl("session").unwrap().is_some());
+//! unqlite.kv_persist("session").unwrap();
+//! assert_eq!(unqlite.kv_ttl("session").unwrap(), None);
+//! # }
+//! # #[cfg(not(feature = "enable-threads"))]
+//! # fn main() { }
+//! ```
+
+use crate::error::{ErrorKind, Result, Wrap};
+use crate::ffi::{unqlite_kv_delete, unqlite_kv_fetch, unqlite_kv_store};
+use crate::{Entry, UnQLite};
+use std::convert::TryInto;
+use std::ptr;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Prefix of the metadata records, never a bucket prefix as `0xff` is not valid UTF-8.
+const META_PREFIX: &[u8] = b"\0\xffttl\0";
+
+/// Present once a time-to-live was set in the database, and prefix of every metadata record.
+const MARKER: &[u8] = b"\0\xffttl";
+
+/// Number of records removed per transaction by `sweep`.
+const SWEEP_BATCH: usize = 1000;
+
+/// States of `UnQLite::ttl`.
+pub(crate) const TTL_UNKNOWN: u8 = 0;
+const TTL_OFF: u8 = 1;
+const TTL_ON: u8 = 2;
+
+fn meta_key(key: &[u8]) -> Vec<u8> {
+    let mut meta = META_PREFIX.to_vec();
+    meta.extend_from_slice(key);
+    meta
+}
+
+/// Milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+/// Expiry time of the record under `key`, `None` if it has no time-to-live.
+pub(crate) fn expires_at(unqlite: &UnQLite, key: &[u8]) -> Option<u64> {
+    let meta = meta_key(key);
+    let mut buf = [0u8; 8];
+    let mut len = buf.len() as i64;
+    wrap_raw!(
+        unqlite,
+        kv_fetch,
+        meta.as_ptr() as _,
+        meta.len() as _,
+        buf.as_mut_ptr() as _,
+        &mut len
+    )
+    .ok()
+    .and_then(|_| decode(&buf[..(len as usize).min(buf.len())]))
+}
+
+/// Decode an expiry time as stored in a metadata record.
+fn decode(at: &[u8]) -> Option<u64> {
+    at.try_into().ok().map(u64::from_le_bytes)
+}
+
+/// Whether the record under `key` has expired.
+pub(crate) fn is_expired(unqlite: &UnQLite, key: &[u8]) -> bool {
+    expires_at(unqlite, key).is_some_and(|at| at <= now())
+}
+
+impl UnQLite {
+    /// Whether a time-to-live was ever set in the database, so expiry must be checked.
+    pub(crate) fn ttl_enabled(&self) -> bool {
+        match self.ttl.load(Ordering::Relaxed) {
+            TTL_ON => true,
+            TTL_OFF => false,
+            _ => {
+                let mut len = 0i64;
+                let found = wrap_raw!(
+                    self,
+                    kv_fetch,
+                    MARKER.as_ptr() as _,
+                    MARKER.len() as _,
+                    ptr::null_mut(),
+                    &mut len
+                )
+                .is_ok();
+                let state = if found { TTL_ON } else { TTL_OFF };
+                self.ttl.store(state, Ordering::Relaxed);
+                found
+            }
+        }
+    }
+
+    /// Whether the record under `key` has expired, and must be missing for reads.
+    ///
+    /// Reads leave the record in place: removing it without the update lock could remove a new
+    /// value stored meanwhile.
+    pub(crate) fn has_expired(&self, key: &[u8]) -> bool {
+        self.ttl_enabled() && is_expired(self, key)
+    }
+
+    /// Remove the record under `key` if it has expired, returns whether it did. To be called
+    /// under the update lock.
+    pub(crate) fn expire(&self, key: &[u8]) -> bool {
+        if !self.has_expired(key) {
+            return false;
+        }
+        // Expired records are missing even if they cannot be removed, in read-only databases.
+        let _ = wrap_raw!(self, kv_delete, key.as_ptr() as _, key.len() as _);
+        let _ = self.clear_ttl(key);
+        true
+    }
+
+    /// Remove the time-to-live of `key`, if any.
+    pub(crate) fn clear_ttl(&self, key: &[u8]) -> Result<()> {
+        let meta = meta_key(key);
+        match wrap_raw!(self, kv_delete, meta.as_ptr() as _, meta.len() as _) {
+            Err(ref err) if err.kind() == Some(ErrorKind::NOTFOUND) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Make `key` expire after `ttl`.
+    pub(crate) fn set_ttl(&self, key: &[u8], ttl: Duration) -> Result<()> {
+        if self.ttl.load(Ordering::Relaxed) != TTL_ON {
+            wrap_raw!(
+                self,
+                kv_store,
+                MARKER.as_ptr() as _,
+                MARKER.len() as _,
+                ptr::null(),
+                0
+            )?;
+            self.ttl.store(TTL_ON, Ordering::Relaxed);
+        }
+        let at = now().saturating_add(ttl.as_millis() as u64).to_le_bytes();
+        let meta = meta_key(key);
+        wrap_raw!(
+            self,
+            kv_store,
+            meta.as_ptr() as _,
+            meta.len() as _,
+            at.as_ptr() as _,
+            at.len() as _
+        )
+    }
+
+    /// Remaining time-to-live of `key`, which exists.
+    pub(crate) fn ttl_of(&self, key: &[u8]) -> Option<Duration> {
+        if !self.ttl_enabled() {
+            return None;
+        }
+        expires_at(self, key)
+            .map(|at| Duration::from_millis(at.saturating_sub(now())))
+    }
+}
+
+/// Remove every expired record, returns how many were removed.
+///
+/// Records are removed by batches, each one committed in its own transaction. While a
+/// transaction begun with [`Transaction::begin`](../trait.Transaction.html#tymethod.begin) is
+/// open, nothing is removed: the sweep stops there, and the next one tries again. Other changes
+/// made on `unqlite` since the last commit are committed before the first batch.
+pub fn sweep(unqlite: &UnQLite) -> Result<usize> {
+    if !unqlite.ttl_enabled() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    loop {
+        let now = now();
+        let mut expired = Vec::new();
+        let mut entry = Entry::first(unqlite, META_PREFIX.to_vec());
+        while let Some(current) = entry {
+            let (key, at) = current.key_value();
+            if decode(&at).is_some_and(|at| at <= now) {
+                expired.push(key);
+                if expired.len() == SWEEP_BATCH {
+                    break;
+                }
+            }
+            entry = current.next();
+        }
+        if expired.is_empty() {
+            return Ok(removed);
+        }
+        let full = expired.len() == SWEEP_BATCH;
+        let batch = unqlite.locked_update_idle(|| {
+            // The time-to-live may have changed since the scan.
+            Ok(expired.iter().filter(|key| unqlite.expire(key)).count())
+        })?;
+        match batch {
+            Some(count) => removed += count,
+            None => return Ok(removed),
+        }
+        if !full {
+            return Ok(removed);
+        }
+    }
+}
+
+/// A thread removing expired records periodically, see [`sweep`](fn.sweep.html).
+///
+/// The thread stops when the `Sweeper` is dropped. Errors are ignored, the next sweep tries
+/// again, and so does it while the application has a transaction open.
+#[derive(Debug)]
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    /// Sweep `unqlite` every `interval`.
+    pub fn spawn(unqlite: Arc<UnQLite>, interval: Duration) -> Sweeper {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let _ = sweep(&unqlite);
+            }
+        });
+        Sweeper {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop the thread and wait for it to finish, returns the panic of the thread if any.
+    pub fn stop(mut self) -> thread::Result<()> {
+        drop(self.stop.take());
+        match self.thread.take() {
+            Some(thread) => thread.join(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "enable-threads")]
+mod tests {
+    use super::{sweep, Sweeper};
+    use crate::{Cursor, Direction, ErrorKind, Transaction, UnQLite, KV};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lazy_expiry() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("plain", "value").unwrap();
+        assert_eq!(unqlite.kv_ttl("plain").unwrap(), None);
+
+        unqlite
+            .kv_store_with_ttl("short", "value", Duration::from_millis(10))
+            .unwrap();
+        unqlite
+            .kv_store_with_ttl("long", "value", Duration::from_secs(3600))
+            .unwrap();
+        let ttl = unqlite.kv_ttl("long").unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(3500));
+        assert!(unqlite.kv_contains("short"));
+
+        thread::sleep(Duration::from_millis(20));
+        let mut entry = unqlite.first();
+        while let Some(current) = entry {
+            assert_ne!(current.key(), b"short");
+            entry = current.next();
+        }
+        assert!(unqlite.seek("short", Direction::Exact).is_none());
+        let err = unqlite.kv_fetch("short").unwrap_err();
+        assert_eq!(err.kind(), Some(ErrorKind::NOTFOUND));
+        assert_eq!(
+            unqlite.kv_ttl("short").unwrap_err().kind(),
+            Some(ErrorKind::NOTFOUND)
+        );
+        // Left to writes and sweeps, which hold the update lock.
+        assert!(super::expires_at(&unqlite, b"short").is_some());
+        assert_eq!(unqlite.kv_incr("short", 1).unwrap(), 1);
+        assert_eq!(unqlite.kv_ttl("short").unwrap(), None);
+
+        unqlite.kv_persist("long").unwrap();
+        assert_eq!(unqlite.kv_ttl("long").unwrap(), None);
+        unqlite
+            .kv_store_with_ttl("long", "value", Duration::from_secs(1))
+            .unwrap();
+        unqlite.kv_store("long", "other").unwrap();
+        assert_eq!(unqlite.kv_ttl("long").unwrap(), None);
+    }
+
+    #[test]
+    fn cursor_delete() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite
+            .kv_store_with_ttl("key", "value", Duration::from_secs(3600))
+            .unwrap();
+        let mut cursor = unqlite.cursor().unwrap();
+        assert!(cursor.seek("key", Direction::Exact));
+        assert!(!cursor.delete_entry());
+        assert_eq!(super::expires_at(&unqlite, b"key"), None);
+        unqlite.kv_append("key", "value").unwrap();
+        assert_eq!(unqlite.kv_ttl("key").unwrap(), None);
+    }
+
+    #[test]
+    fn sweeper() {
+        let unqlite = Arc::new(UnQLite::create_in_memory());
+        for i in 0..10 {
+            unqlite
+                .kv_store_with_ttl(i.to_string(), "value", Duration::from_millis(1))
+                .unwrap();
+        }
+        unqlite.kv_store("kept", "value").unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(sweep(&unqlite).unwrap(), 10);
+        assert_eq!(sweep(&unqlite).unwrap(), 0);
+
+        unqlite
+            .kv_store_with_ttl("key", "value", Duration::from_millis(1))
+            .unwrap();
+        let sweeper = Sweeper::spawn(unqlite.clone(), Duration::from_millis(5));
+        thread::sleep(Duration::from_millis(50));
+        sweeper.stop().unwrap();
+        assert_eq!(super::expires_at(&unqlite, b"key"), None);
+        assert!(unqlite.kv_contains("kept"));
+
+        unqlite
+            .kv_store_with_ttl("key", "value", Duration::from_millis(1))
+            .unwrap();
+        unqlite.begin().unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(sweep(&unqlite).unwrap(), 0);
+        unqlite.commit().unwrap();
+        assert_eq!(sweep(&unqlite).unwrap(), 1);
+    }
+
+    #[test]
+    fn metadata_hidden() {
+        let unqlite = UnQLite::create_in_memory();
+        unqlite.kv_store("plain", "value").unwrap();
+        unqlite
+            .kv_store_with_ttl("expiring", "value", Duration::from_secs(3600))
+            .unwrap();
+        assert_eq!(unqlite.iter().count(), 2);
+        assert_eq!(unqlite.iter().rev().count(), 2);
+        let mut cursor = unqlite.cursor().unwrap();
+        let mut keys = Vec::new();
+        let mut valid = cursor.last_entry();
+        while valid {
+            keys.push(cursor.key().unwrap());
+            valid = cursor.prev_entry();
+        }
+        assert_eq!(keys.len(), 2);
+    }
+}
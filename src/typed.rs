@@ -0,0 +1,260 @@
+//! Typed Key/Value stores.
+//!
+//! [`TypedStore`](struct.TypedStore.html) sits on top of any [`KV`](../trait.KV.html), a
+//! database or a [`Bucket`](../struct.Bucket.html), and converts keys and values to and from
+//! bytes with a [`Codec`](trait.Codec.html). Besides
+//! [`Raw`](struct.Raw.html) for plain bytes and strings, codecs for any `serde` type are
+//! available behind cargo features:
+//!
+//! Feature | Codec
+//! --- | ---
+//! `json` | `Json`, with `serde_json`
+//! `bincode` | `Bincode`, with `bincode`
+//! `cbor` | `Cbor`, with `ciborium`
+//!
+//! ```
+//! # extern crate unqlite;
+//! #
+//! # #[cfg(all(feature = "enable-threads", feature = "json"))]
+//! # fn main() {
+//! use unqlite::typed::{Json, TypedStore};
+//! use unqlite::UnQLite;
+//!
+//! let unqlite = UnQLite::create_in_memory();
+//! let bucket = unqlite.bucket("users").unwrap();
+//! let users: TypedStore<u64, (String, u32), Json, _> = TypedStore::new(&bucket);
+//! users.insert(&1, &("Alice".to_owned(), 42)).unwrap();
+//! assert_eq!(users.get(&1).unwrap().unwrap().1, 42);
+//! assert!(!users.contains(&2).unwrap());
+//! # }
+//! # #[cfg(not(all(feature = "enable-threads", feature = "json")))]
+//! # fn main() { }
+//! ```
+//!
+//! Encoding and decoding errors are reported as `Error::Other`.
+
+use crate::error::{ErrorKind, Result};
+use crate::{UnQLite, KV};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Conversion of `T` to and from bytes.
+pub trait Codec<T> {
+    /// Encode `value` as bytes.
+    fn encode(value: &T) -> Result<Vec<u8>>;
+
+    /// Decode a value from `bytes`.
+    fn decode(bytes: Vec<u8>) -> Result<T>;
+}
+
+/// Store bytes and strings as is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Raw;
+
+impl Codec<Vec<u8>> for Raw {
+    fn encode(value: &Vec<u8>) -> Result<Vec<u8>> {
+        Ok(value.clone())
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(bytes)
+    }
+}
+
+impl Codec<String> for Raw {
+    fn encode(value: &String) -> Result<Vec<u8>> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<String> {
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+/// Encode with `serde_json`.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<T> {
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// Encode with `bincode`.
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Bincode {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<T> {
+        Ok(bincode::deserialize(&bytes)?)
+    }
+}
+
+/// Encode as CBOR with `ciborium`.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Cbor {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<T> {
+        Ok(ciborium::de::from_reader(bytes.as_slice())?)
+    }
+}
+
+/// A view of a Key/Value store `S` as a store of `K` to `V`, encoded with `C`.
+pub struct TypedStore<'db, K, V, C = Raw, S = UnQLite> {
+    store: &'db S,
+    marker: PhantomData<fn(K, V, C)>,
+}
+
+impl<'db, K, V, C, S> TypedStore<'db, K, V, C, S>
+where
+    C: Codec<K> + Codec<V>,
+    S: KV,
+{
+    /// Create a typed view of `store`.
+    pub fn new(store: &'db S) -> Self {
+        TypedStore {
+            store,
+            marker: PhantomData,
+        }
+    }
+
+    /// Store `value` under `key`, replacing any existing record.
+    pub fn insert(&self, key: &K, value: &V) -> Result<()> {
+        self.store.kv_store(
+            <C as Codec<K>>::encode(key)?,
+            <C as Codec<V>>::encode(value)?,
+        )
+    }
+
+    /// Fetch and decode the value under `key`, `None` if there is no such record.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.store.kv_fetch(<C as Codec<K>>::encode(key)?) {
+            Ok(bytes) => <C as Codec<V>>::decode(bytes).map(Some),
+            Err(ref err) if err.kind() == Some(ErrorKind::NOTFOUND) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Check if there is a record under `key`.
+    pub fn contains(&self, key: &K) -> Result<bool> {
+        match self.store.kv_fetch_length(<C as Codec<K>>::encode(key)?) {
+            Ok(_) => Ok(true),
+            Err(ref err) if err.kind() == Some(ErrorKind::NOTFOUND) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Remove the record under `key`.
+    pub fn remove(&self, key: &K) -> Result<()> {
+        self.store.kv_delete(<C as Codec<K>>::encode(key)?)
+    }
+
+    /// The underlying store.
+    pub fn store(&self) -> &'db S {
+        self.store
+    }
+}
+
+impl<'db, K, V, C, S> Clone for TypedStore<'db, K, V, C, S> {
+    fn clone(&self) -> Self {
+        TypedStore {
+            store: self.store,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'db, K, V, C, S> fmt::Debug for TypedStore<'db, K, V, C, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TypedStore").finish()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "enable-threads")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw() {
+        let unqlite = UnQLite::create_in_memory();
+        let store: TypedStore<String, Vec<u8>> = TypedStore::new(&unqlite);
+        store.insert(&"key".to_owned(), &vec![1, 2, 3]).unwrap();
+        assert_eq!(store.get(&"key".to_owned()).unwrap(), Some(vec![1, 2, 3]));
+        assert!(store.contains(&"key".to_owned()).unwrap());
+        store.remove(&"key".to_owned()).unwrap();
+        assert_eq!(store.get(&"key".to_owned()).unwrap(), None);
+
+        unqlite.kv_store("invalid", [0xff]).unwrap();
+        let store: TypedStore<String, String> = TypedStore::new(&unqlite);
+        let err = store.get(&"invalid".to_owned()).unwrap_err();
+        assert_eq!(err.kind(), None);
+    }
+
+    #[test]
+    fn bucket() {
+        let unqlite = UnQLite::create_in_memory();
+        let bucket = unqlite.bucket("typed").unwrap();
+        let store: TypedStore<String, String, Raw, _> = TypedStore::new(&bucket);
+        store.insert(&"key".to_owned(), &"value".to_owned()).unwrap();
+        assert!(store.contains(&"key".to_owned()).unwrap());
+        assert_eq!(bucket.kv_fetch("key").unwrap(), b"value");
+        assert!(!unqlite.kv_contains("key"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        let unqlite = UnQLite::create_in_memory();
+        let store: TypedStore<(u32, String), Vec<f64>, Json> = TypedStore::new(&unqlite);
+        let key = (1, "one".to_owned());
+        store.insert(&key, &vec![1.0, 1.5]).unwrap();
+        assert_eq!(store.get(&key).unwrap(), Some(vec![1.0, 1.5]));
+        assert_eq!(unqlite.kv_fetch(r#"[1,"one"]"#).unwrap(), b"[1.0,1.5]");
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode() {
+        let unqlite = UnQLite::create_in_memory();
+        let store: TypedStore<u64, Option<String>, Bincode> = TypedStore::new(&unqlite);
+        store.insert(&7, &Some("seven".to_owned())).unwrap();
+        assert_eq!(store.get(&7).unwrap(), Some(Some("seven".to_owned())));
+        assert_eq!(store.get(&8).unwrap(), None);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor() {
+        let unqlite = UnQLite::create_in_memory();
+        let store: TypedStore<String, (bool, i32), Cbor> = TypedStore::new(&unqlite);
+        store.insert(&"key".to_owned(), &(true, -1)).unwrap();
+        assert_eq!(store.get(&"key".to_owned()).unwrap(), Some((true, -1)));
+
+        let key = <Cbor as Codec<String>>::encode(&"bad".to_owned()).unwrap();
+        unqlite.kv_store(key, [0xff]).unwrap();
+        assert!(store.get(&"bad".to_owned()).is_err());
+    }
+}
@@ -4,9 +4,9 @@ use crate::ffi::{
     unqlite_util_release_mmaped_file,
 };
 use std::ffi::CString;
-use std::mem;
 use std::os::raw::c_void;
 use std::path::Path;
+use std::ptr;
 use crate::UnQLite;
 
 /// Utility interfaces.
@@ -48,7 +48,7 @@ impl Util for UnQLite {
 pub fn load_mmaped_file<P: AsRef<Path>>(path: P) -> Result<Mmap> {
     unsafe {
         let path = path.as_ref();
-        let mut ptr: *mut c_void = mem::MaybeUninit::uninit().assume_init();
+        let mut ptr: *mut c_void = ptr::null_mut();
         let mut size: i64 = 0;
         let cpath = CString::new(
             path.to_str().expect("cannot convert the path to str")
@@ -56,8 +56,8 @@ pub fn load_mmaped_file<P: AsRef<Path>>(path: P) -> Result<Mmap> {
         unqlite_util_load_mmaped_file(cpath.as_ptr(), &mut ptr, &mut size)
             .wrap()
             .map(|_| Mmap {
-                ptr: ptr,
-                size: size,
+                ptr,
+                size,
             })
     }
 }
@@ -78,7 +78,6 @@ impl Drop for Mmap {
 #[cfg(feature = "enable-threads")]
 mod tests {
     use super::*;
-    use UnQLite;
 
     #[test]
     fn test_random_string() {
@@ -98,7 +97,7 @@ mod tests {
         use tempfile::NamedTempFile;
         let mut f = NamedTempFile::new().expect("get named temp file");
         let _ = f.write_all(b"Hello, world!");
-        let _ = f.sync_all();
+        let _ = f.as_file().sync_all();
         load_mmaped_file(f.path()).unwrap();
     }
 }
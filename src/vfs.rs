@@ -0,0 +1,772 @@
+//! Virtual file system interfaces.
+//!
+//! UnQLite performs all of its disk I/O through a virtual file system (VFS). By default the
+//! engine uses its own OS layer, [`register`](fn.register.html) installs a VFS written in Rust
+//! instead:
+//!
+//! ```no_run
+//! # extern crate unqlite;
+//! #
+//! use unqlite::vfs::{self, MemoryVfs};
+//! use unqlite::{OpenOptions, KV};
+//!
+//! # fn main() {
+//! // Must happen before the first database is opened.
+//! vfs::register(MemoryVfs::new()).unwrap();
+//!
+//! let unqlite = OpenOptions::new().create(true).open("test.db").unwrap();
+//! unqlite.kv_store("key", "value").unwrap();
+//! # }
+//! ```
+//!
+//! Two implementations are bundled: [`MemoryVfs`](struct.MemoryVfs.html) keeps every file in
+//! RAM and [`OsVfs`](struct.OsVfs.html) passes through to `std::fs`. Implement
+//! [`Vfs`](trait.Vfs.html) and [`VfsFile`](trait.VfsFile.html) to layer your own storage under
+//! the engine.
+//!
+//! Note that `:mem:` and in-memory databases never touch the VFS.
+
+use crate::error::{Result, Wrap};
+use crate::ffi::{unqlite_file, unqlite_io_methods, unqlite_lib_config, unqlite_vfs, Sytm};
+use crate::vars::{
+    UNQLITE_ACCESS_EXISTS, UNQLITE_ACCESS_READ, UNQLITE_ACCESS_READWRITE, UNQLITE_BUSY,
+    UNQLITE_CANTOPEN, UNQLITE_IOERR, UNQLITE_LIB_CONFIG_VFS, UNQLITE_LOCK_EXCLUSIVE,
+    UNQLITE_LOCK_NONE, UNQLITE_LOCK_PENDING, UNQLITE_LOCK_RESERVED, UNQLITE_LOCK_SHARED,
+    UNQLITE_NOTFOUND, UNQLITE_OK, UNQLITE_OPEN_CREATE, UNQLITE_OPEN_EXCLUSIVE,
+    UNQLITE_OPEN_READONLY, UNQLITE_OPEN_TEMP_DB, UNQLITE_PERM,
+};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Lock levels requested by the pager, weakest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    None = UNQLITE_LOCK_NONE as isize,
+    Shared = UNQLITE_LOCK_SHARED as isize,
+    Reserved = UNQLITE_LOCK_RESERVED as isize,
+    Pending = UNQLITE_LOCK_PENDING as isize,
+    Exclusive = UNQLITE_LOCK_EXCLUSIVE as isize,
+}
+
+impl LockLevel {
+    fn from_raw(level: c_int) -> LockLevel {
+        match level as c_uint {
+            UNQLITE_LOCK_SHARED => LockLevel::Shared,
+            UNQLITE_LOCK_RESERVED => LockLevel::Reserved,
+            UNQLITE_LOCK_PENDING => LockLevel::Pending,
+            UNQLITE_LOCK_EXCLUSIVE => LockLevel::Exclusive,
+            _ => LockLevel::None,
+        }
+    }
+}
+
+/// Kinds of access checked by `Vfs::access`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// The file exists
+    Exists = UNQLITE_ACCESS_EXISTS as isize,
+    /// The file is both readable and writable
+    ReadWrite = UNQLITE_ACCESS_READWRITE as isize,
+    /// The file is readable
+    Read = UNQLITE_ACCESS_READ as isize,
+}
+
+/// A virtual file system.
+///
+/// Paths are handed over exactly as the engine builds them, i.e. the database file name given
+/// to `open` and the same name suffixed with `_unqlite_journal` for its journal.
+pub trait Vfs: Send + Sync + 'static {
+    /// Open (or create) the file at `path`.
+    ///
+    /// `flags` is a combination of the `UNQLITE_OPEN_*` constants found in
+    /// [`vars`](../vars/index.html).
+    fn open(&self, path: &str, flags: u32) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Remove the file at `path`.
+    fn delete(&self, path: &str, sync_dir: bool) -> io::Result<()>;
+
+    /// Check whether `path` is accessible.
+    fn access(&self, path: &str, access: Access) -> io::Result<bool>;
+
+    /// Canonical form of `path`.
+    fn full_pathname(&self, path: &str) -> String {
+        path.to_owned()
+    }
+
+    /// Directory where temporary databases are created.
+    fn tmp_dir(&self) -> String {
+        env::temp_dir().to_string_lossy().into_owned()
+    }
+
+    /// Sleep for about `duration`, returns how long was actually slept.
+    fn sleep(&self, duration: Duration) -> Duration {
+        thread::sleep(duration);
+        duration
+    }
+}
+
+/// An open file of a `Vfs`.
+pub trait VfsFile: Send {
+    /// Read up to `buf.len()` bytes at `offset`, returns the number of bytes read.
+    ///
+    /// Reading less than requested is reported to the engine as an I/O error.
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Write the whole `buf` at `offset`, growing the file as needed.
+    fn write(&mut self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Truncate (or extend) the file to `size` bytes.
+    fn truncate(&mut self, size: u64) -> io::Result<()>;
+
+    /// Flush the file content to the storage.
+    fn sync(&mut self) -> io::Result<()>;
+
+    /// Current size of the file.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Upgrade the lock on the file to `level`.
+    ///
+    /// Returns `false` if the lock is held by someone else, which makes the engine report
+    /// `ErrorKind::BUSY`. The default implementation does no locking at all.
+    fn lock(&mut self, _level: LockLevel) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Downgrade the lock on the file to `level` (`Shared` or `None`).
+    fn unlock(&mut self, _level: LockLevel) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Check whether anyone holds a `Reserved` (or stronger) lock on the file.
+    fn check_reserved_lock(&self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Sector size of the underlying storage.
+    fn sector_size(&self) -> u32 {
+        512
+    }
+}
+
+/// Install `vfs` as the file system used by every database opened from now on.
+///
+/// This must be called before the first database is opened, otherwise the library is already
+/// initialized and `ErrorKind::LOCKED` is returned.
+pub fn register<V: Vfs>(vfs: V) -> Result<()> {
+    let raw = Box::into_raw(Box::new(RawVfs {
+        base: unqlite_vfs {
+            zName: VFS_NAME.as_ptr() as *const c_char,
+            iVersion: 1,
+            szOsFile: mem::size_of::<RawFile>() as c_int,
+            mxPathname: MAX_PATHNAME,
+            xOpen: Some(vfs_open),
+            xDelete: Some(vfs_delete),
+            xAccess: Some(vfs_access),
+            xFullPathname: Some(vfs_full_pathname),
+            xTmpDir: Some(vfs_tmp_dir),
+            xSleep: Some(vfs_sleep),
+            xCurrentTime: Some(vfs_current_time),
+            xGetLastError: Some(vfs_get_last_error),
+        },
+        vfs: Box::new(vfs),
+    }));
+    // The engine keeps the pointer forever: leak it on success.
+    unsafe { unqlite_lib_config(UNQLITE_LIB_CONFIG_VFS, raw as *mut unqlite_vfs) }
+        .wrap()
+        .inspect_err(|_| drop(unsafe { Box::from_raw(raw) }))
+}
+
+/// A file system living entirely in RAM.
+///
+/// Clones share the same files, so a handle can be kept to inspect what the engine wrote.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryVfs {
+    files: Arc<Mutex<HashMap<String, SharedData>>>,
+}
+
+type SharedData = Arc<Mutex<Vec<u8>>>;
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        MemoryVfs::default()
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &str, flags: u32) -> io::Result<Box<dyn VfsFile>> {
+        let mut files = self.files.lock().unwrap();
+        let data = match files.get(path) {
+            Some(_) if flags & UNQLITE_OPEN_EXCLUSIVE != 0 => {
+                return Err(io::ErrorKind::AlreadyExists.into())
+            }
+            Some(data) => data.clone(),
+            None if flags & UNQLITE_OPEN_CREATE == 0 => return Err(io::ErrorKind::NotFound.into()),
+            None => files.entry(path.to_owned()).or_default().clone(),
+        };
+        Ok(Box::new(MemoryFile { data }))
+    }
+
+    fn delete(&self, path: &str, _sync_dir: bool) -> io::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    fn access(&self, path: &str, _access: Access) -> io::Result<bool> {
+        Ok(self.files.lock().unwrap().contains_key(path))
+    }
+}
+
+struct MemoryFile {
+    data: SharedData,
+}
+
+impl VfsFile for MemoryFile {
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> io::Result<()> {
+        self.data.lock().unwrap().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.lock().unwrap().len() as u64)
+    }
+}
+
+/// A pass-through file system on top of `std::fs`.
+///
+/// On Unix, files are protected with whole-file advisory locks (`flock`), so several processes
+/// may share a database. Elsewhere no locking is done at all.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &str, flags: u32) -> io::Result<Box<dyn VfsFile>> {
+        let mut options = fs::OpenOptions::new();
+        options.read(true);
+        if flags & UNQLITE_OPEN_READONLY == 0 {
+            options.write(true);
+        }
+        if flags & UNQLITE_OPEN_EXCLUSIVE != 0 {
+            options.write(true).create_new(true);
+        } else if flags & UNQLITE_OPEN_CREATE != 0 {
+            options.write(true).create(true);
+        }
+        Ok(Box::new(OsFile {
+            path: path.to_owned(),
+            file: options.open(path)?,
+            lock: LockLevel::None,
+        }))
+    }
+
+    fn delete(&self, path: &str, _sync_dir: bool) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn access(&self, path: &str, access: Access) -> io::Result<bool> {
+        match fs::metadata(path) {
+            Ok(meta) => Ok(access != Access::ReadWrite || !meta.permissions().readonly()),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn full_pathname(&self, path: &str) -> String {
+        env::current_dir()
+            .map(|dir| dir.join(path).to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_owned())
+    }
+}
+
+struct OsFile {
+    path: String,
+    file: File,
+    lock: LockLevel,
+}
+
+impl VfsFile for OsFile {
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        use std::io::{Read, Seek, SeekFrom};
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut total = 0;
+        while total < buf.len() {
+            match self.file.read(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(total)
+    }
+
+    fn write(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        use std::io::{Seek, SeekFrom, Write};
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(buf)
+    }
+
+    fn truncate(&mut self, size: u64) -> io::Result<()> {
+        self.file.set_len(size)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.file.metadata().map(|meta| meta.len())
+    }
+
+    #[cfg(unix)]
+    fn lock(&mut self, level: LockLevel) -> io::Result<bool> {
+        let operation = match level {
+            LockLevel::None => libc::LOCK_UN,
+            LockLevel::Shared => libc::LOCK_SH,
+            _ => libc::LOCK_EX,
+        };
+        if flock(&self.file, operation | libc::LOCK_NB)? {
+            self.lock = level;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    #[cfg(unix)]
+    fn unlock(&mut self, level: LockLevel) -> io::Result<()> {
+        if level < self.lock {
+            self.lock(level).map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(unix)]
+    fn check_reserved_lock(&self) -> io::Result<bool> {
+        if self.lock >= LockLevel::Reserved {
+            return Ok(true);
+        }
+        // `flock` locks belong to the open file description, probe through a fresh one. Reserved
+        // and higher levels are exclusive locks, so a shared probe only fails on those and is
+        // not blocked by the shared lock this handle may hold.
+        let probe = File::open(&self.path)?;
+        let free = flock(&probe, libc::LOCK_SH | libc::LOCK_NB)?;
+        Ok(!free)
+    }
+}
+
+/// Returns `false` if the lock would block.
+#[cfg(unix)]
+fn flock(file: &File, operation: c_int) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+const VFS_NAME: &[u8] = b"unqlite-rs\0";
+const MAX_PATHNAME: c_int = 512;
+
+/// `unqlite_vfs` followed by the Rust implementation, so callbacks can get back to it.
+#[repr(C)]
+struct RawVfs {
+    base: unqlite_vfs,
+    vfs: Box<dyn Vfs>,
+}
+
+/// `unqlite_file` subclass, allocated by the engine with `szOsFile` bytes.
+#[repr(C)]
+struct RawFile {
+    base: unqlite_file,
+    file: Box<dyn VfsFile>,
+    /// Set for temporary files, which are deleted on close
+    temp: Option<(*const RawVfs, String)>,
+}
+
+static IO_METHODS: unqlite_io_methods = unqlite_io_methods {
+    iVersion: 1,
+    xClose: Some(file_close),
+    xRead: Some(file_read),
+    xWrite: Some(file_write),
+    xTruncate: Some(file_truncate),
+    xSync: Some(file_sync),
+    xFileSize: Some(file_size),
+    xLock: Some(file_lock),
+    xUnlock: Some(file_unlock),
+    xCheckReservedLock: Some(file_check_reserved_lock),
+    xSectorSize: Some(file_sector_size),
+};
+
+/// Run `f`, turning errors and panics into an UnQLite return code.
+fn guard<F: FnOnce() -> io::Result<c_int>>(f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(rc)) => rc,
+        Ok(Err(err)) => match err.kind() {
+            io::ErrorKind::NotFound => UNQLITE_NOTFOUND,
+            io::ErrorKind::PermissionDenied => UNQLITE_PERM,
+            _ => UNQLITE_IOERR,
+        },
+        Err(_) => UNQLITE_IOERR,
+    }
+}
+
+unsafe fn vfs<'a>(raw: *mut unqlite_vfs) -> &'a dyn Vfs {
+    &*(*(raw as *const RawVfs)).vfs
+}
+
+unsafe fn file<'a>(raw: *mut unqlite_file) -> &'a mut RawFile {
+    &mut *(raw as *mut RawFile)
+}
+
+unsafe fn to_str<'a>(name: *const c_char) -> io::Result<&'a str> {
+    CStr::from_ptr(name)
+        .to_str()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// Copy `src` as a nul-terminated string into the engine buffer `buf`.
+unsafe fn copy_to(src: &str, buf: *mut c_char, buf_len: c_int) -> c_int {
+    if buf_len <= 0 {
+        return UNQLITE_IOERR;
+    }
+    let len = src.len().min(buf_len as usize - 1);
+    ptr::copy_nonoverlapping(src.as_ptr() as *const c_char, buf, len);
+    *buf.add(len) = 0;
+    UNQLITE_OK
+}
+
+unsafe extern "C" fn vfs_open(
+    raw: *mut unqlite_vfs,
+    name: *const c_char,
+    out: *mut unqlite_file,
+    flags: c_uint,
+) -> c_int {
+    static TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+    let vfs = vfs(raw);
+    let rc = guard(|| {
+        let (path, temp) = if name.is_null() || flags & UNQLITE_OPEN_TEMP_DB != 0 {
+            let path = format!(
+                "{}/unqlite_{}_{}",
+                vfs.tmp_dir(),
+                std::process::id(),
+                TEMP_ID.fetch_add(1, Ordering::SeqCst)
+            );
+            (path.clone(), Some((raw as *const RawVfs, path)))
+        } else {
+            (to_str(name)?.to_owned(), None)
+        };
+        let flags = if temp.is_some() {
+            flags | UNQLITE_OPEN_CREATE
+        } else {
+            flags
+        };
+        let file = vfs.open(&path, flags)?;
+        ptr::write(
+            out as *mut RawFile,
+            RawFile {
+                base: unqlite_file {
+                    pMethods: &IO_METHODS,
+                },
+                file,
+                temp,
+            },
+        );
+        Ok(UNQLITE_OK)
+    });
+    if rc == UNQLITE_OK {
+        rc
+    } else {
+        UNQLITE_CANTOPEN
+    }
+}
+
+unsafe extern "C" fn vfs_delete(raw: *mut unqlite_vfs, name: *const c_char, sync: c_int) -> c_int {
+    guard(|| {
+        vfs(raw)
+            .delete(to_str(name)?, sync != 0)
+            .map(|_| UNQLITE_OK)
+    })
+}
+
+unsafe extern "C" fn vfs_access(
+    raw: *mut unqlite_vfs,
+    name: *const c_char,
+    flags: c_int,
+    out: *mut c_int,
+) -> c_int {
+    let access = match flags as c_uint {
+        UNQLITE_ACCESS_READWRITE => Access::ReadWrite,
+        UNQLITE_ACCESS_READ => Access::Read,
+        _ => Access::Exists,
+    };
+    guard(|| {
+        *out = vfs(raw).access(to_str(name)?, access)? as c_int;
+        Ok(UNQLITE_OK)
+    })
+}
+
+unsafe extern "C" fn vfs_full_pathname(
+    raw: *mut unqlite_vfs,
+    name: *const c_char,
+    buf_len: c_int,
+    buf: *mut c_char,
+) -> c_int {
+    guard(|| {
+        Ok(copy_to(
+            &vfs(raw).full_pathname(to_str(name)?),
+            buf,
+            buf_len,
+        ))
+    })
+}
+
+unsafe extern "C" fn vfs_tmp_dir(raw: *mut unqlite_vfs, buf: *mut c_char, buf_len: c_int) -> c_int {
+    guard(|| Ok(copy_to(&vfs(raw).tmp_dir(), buf, buf_len)))
+}
+
+unsafe extern "C" fn vfs_sleep(raw: *mut unqlite_vfs, microseconds: c_int) -> c_int {
+    let duration = Duration::from_micros(microseconds.max(0) as u64);
+    panic::catch_unwind(AssertUnwindSafe(|| vfs(raw).sleep(duration)))
+        .map(|slept| slept.as_micros() as c_int)
+        .unwrap_or(0)
+}
+
+unsafe extern "C" fn vfs_current_time(_raw: *mut unqlite_vfs, out: *mut Sytm) -> c_int {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or(0);
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400) as c_int;
+    let (year, month, day) = civil_from_days(days);
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    const YDAYS: [c_int; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let yday = YDAYS[month as usize - 1] + day - 1 + if leap && month > 2 { 1 } else { 0 };
+
+    let tm = &mut *out;
+    tm.tm_sec = rem % 60;
+    tm.tm_min = rem / 60 % 60;
+    tm.tm_hour = rem / 3600;
+    tm.tm_mday = day;
+    tm.tm_mon = month - 1;
+    tm.tm_year = year as c_int;
+    tm.tm_wday = (days + 4).rem_euclid(7) as c_int;
+    tm.tm_yday = yday;
+    tm.tm_isdst = 0;
+    tm.tm_zone = ptr::null_mut::<c_char>() as _;
+    tm.tm_gmtoff = 0;
+    UNQLITE_OK
+}
+
+unsafe extern "C" fn vfs_get_last_error(_: *mut unqlite_vfs, _: c_int, _: *mut c_char) -> c_int {
+    0
+}
+
+/// Gregorian `(year, month, day)` of a day count since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, c_int, c_int) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as c_int;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as c_int;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+unsafe extern "C" fn file_close(raw: *mut unqlite_file) -> c_int {
+    let RawFile { file, temp, .. } = ptr::read(raw as *mut RawFile);
+    guard(move || {
+        drop(file);
+        if let Some((vfs, path)) = temp {
+            (*vfs).vfs.delete(&path, false)?;
+        }
+        Ok(UNQLITE_OK)
+    })
+}
+
+unsafe extern "C" fn file_read(
+    raw: *mut unqlite_file,
+    buf: *mut c_void,
+    amount: i64,
+    offset: i64,
+) -> c_int {
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, amount as usize);
+    guard(|| {
+        let read = file(raw).file.read(buf, offset as u64)?;
+        if read == buf.len() {
+            Ok(UNQLITE_OK)
+        } else {
+            // Short read: unread parts of the buffer must be zero-filled
+            for byte in &mut buf[read..] {
+                *byte = 0;
+            }
+            Ok(UNQLITE_IOERR)
+        }
+    })
+}
+
+unsafe extern "C" fn file_write(
+    raw: *mut unqlite_file,
+    buf: *const c_void,
+    amount: i64,
+    offset: i64,
+) -> c_int {
+    let buf = slice::from_raw_parts(buf as *const u8, amount as usize);
+    guard(|| file(raw).file.write(buf, offset as u64).map(|_| UNQLITE_OK))
+}
+
+unsafe extern "C" fn file_truncate(raw: *mut unqlite_file, size: i64) -> c_int {
+    guard(|| file(raw).file.truncate(size as u64).map(|_| UNQLITE_OK))
+}
+
+unsafe extern "C" fn file_sync(raw: *mut unqlite_file, _flags: c_int) -> c_int {
+    guard(|| file(raw).file.sync().map(|_| UNQLITE_OK))
+}
+
+unsafe extern "C" fn file_size(raw: *mut unqlite_file, out: *mut i64) -> c_int {
+    guard(|| {
+        *out = file(raw).file.size()? as i64;
+        Ok(UNQLITE_OK)
+    })
+}
+
+unsafe extern "C" fn file_lock(raw: *mut unqlite_file, level: c_int) -> c_int {
+    guard(|| {
+        file(raw)
+            .file
+            .lock(LockLevel::from_raw(level))
+            .map(|locked| if locked { UNQLITE_OK } else { UNQLITE_BUSY })
+    })
+}
+
+unsafe extern "C" fn file_unlock(raw: *mut unqlite_file, level: c_int) -> c_int {
+    guard(|| {
+        file(raw)
+            .file
+            .unlock(LockLevel::from_raw(level))
+            .map(|_| UNQLITE_OK)
+    })
+}
+
+unsafe extern "C" fn file_check_reserved_lock(raw: *mut unqlite_file, out: *mut c_int) -> c_int {
+    guard(|| {
+        *out = file(raw).file.check_reserved_lock()? as c_int;
+        Ok(UNQLITE_OK)
+    })
+}
+
+unsafe extern "C" fn file_sector_size(raw: *mut unqlite_file) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(|| file(raw).file.sector_size() as c_int)).unwrap_or(512)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vars::UNQLITE_OPEN_READWRITE;
+
+    #[test]
+    fn memory_vfs() {
+        let vfs = MemoryVfs::new();
+        assert!(vfs.open("a.db", UNQLITE_OPEN_READWRITE).is_err());
+
+        let mut file = vfs.open("a.db", UNQLITE_OPEN_CREATE).unwrap();
+        file.write(b"world", 6).unwrap();
+        file.write(b"hello", 0).unwrap();
+        assert_eq!(file.size().unwrap(), 11);
+        assert!(vfs.access("a.db", Access::Exists).unwrap());
+        assert!(vfs
+            .open("a.db", UNQLITE_OPEN_CREATE | UNQLITE_OPEN_EXCLUSIVE)
+            .is_err());
+
+        let mut other = vfs.clone().open("a.db", UNQLITE_OPEN_READWRITE).unwrap();
+        let mut buf = [0xff; 16];
+        assert_eq!(other.read(&mut buf, 0).unwrap(), 11);
+        assert_eq!(&buf[..11], b"hello\0world");
+
+        other.truncate(5).unwrap();
+        assert_eq!(file.size().unwrap(), 5);
+        vfs.delete("a.db", false).unwrap();
+        assert!(!vfs.access("a.db", Access::Exists).unwrap());
+    }
+
+    #[test]
+    fn os_vfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.db");
+        let path = path.to_str().unwrap();
+
+        assert!(!OsVfs.access(path, Access::Exists).unwrap());
+        let mut file = OsVfs.open(path, UNQLITE_OPEN_CREATE).unwrap();
+        file.write(b"hello", 0).unwrap();
+        file.sync().unwrap();
+        assert!(file.lock(LockLevel::Shared).unwrap());
+        assert!(!file.check_reserved_lock().unwrap());
+        assert!(file.lock(LockLevel::Exclusive).unwrap());
+        file.unlock(LockLevel::None).unwrap();
+
+        let mut other = OsVfs.open(path, UNQLITE_OPEN_READWRITE).unwrap();
+        assert!(other.lock(LockLevel::Reserved).unwrap());
+        assert!(file.check_reserved_lock().unwrap());
+        other.unlock(LockLevel::None).unwrap();
+        assert!(!file.check_reserved_lock().unwrap());
+
+        let mut buf = [0; 8];
+        assert_eq!(file.read(&mut buf, 0).unwrap(), 5);
+        assert!(OsVfs.access(path, Access::ReadWrite).unwrap());
+        OsVfs.delete(path, false).unwrap();
+        assert!(!OsVfs.access(path, Access::Exists).unwrap());
+    }
+
+    #[test]
+    fn current_time() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
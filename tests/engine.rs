@@ -0,0 +1,66 @@
+//! Storage engines are process-wide and can only be registered before the library is
+//! initialized, so this lives in its own test binary.
+extern crate unqlite;
+
+use unqlite::engine::{self, BTreeEngine};
+use unqlite::{Config, Cursor, Direction, ErrorKind, UnQLite, WriteBatch, KV};
+
+#[test]
+fn btree_engine() {
+    engine::register("btree", BTreeEngine::default).expect("register before the first open");
+
+    let unqlite = UnQLite::create_in_memory().kv_engine("btree");
+    assert_eq!(unqlite.kv_name(), "btree");
+
+    for key in &["c", "a", "d", "b"] {
+        unqlite.kv_store(key, key.to_uppercase()).unwrap();
+    }
+    unqlite.kv_append("a", "A").unwrap();
+    assert_eq!(unqlite.kv_fetch("a").unwrap(), b"AA");
+
+    let mut keys = Vec::new();
+    let mut entry = unqlite.first();
+    while let Some(record) = entry {
+        keys.push(record.key());
+        entry = record.next();
+    }
+    assert_eq!(keys, vec![b"a", b"b", b"c", b"d"]);
+
+    unqlite.kv_delete("b").unwrap();
+    assert!(!unqlite.kv_contains("b"));
+    assert_eq!(unqlite.seek("b", Direction::Ge).unwrap().key(), b"c");
+    assert_eq!(unqlite.last().unwrap().key(), b"d");
+
+    assert!(unqlite.is_ordered());
+    fn range_keys<I>(range: I) -> Vec<Vec<u8>>
+    where
+        I: Iterator<Item = unqlite::Result<(Vec<u8>, Vec<u8>)>>,
+    {
+        range.map(|record| record.unwrap().0).collect()
+    }
+    assert_eq!(range_keys(unqlite.range("a".."d")), vec![b"a", b"c"]);
+    assert_eq!(range_keys(unqlite.range("aa"..="d").rev()), vec![b"d", b"c"]);
+    assert_eq!(range_keys(unqlite.range(.."c")), vec![b"a"]);
+    let mut range = unqlite.range::<&str, _>(..);
+    assert_eq!(range.next().unwrap().unwrap().0, b"a");
+    assert_eq!(range.next_back().unwrap().unwrap().0, b"d");
+    assert_eq!(range.next().unwrap().unwrap().0, b"c");
+    assert!(range.next_back().is_none());
+
+    unqlite.kv_store("ca", "CA").unwrap();
+    unqlite.kv_store("cb", "CB").unwrap();
+    assert_eq!(range_keys(unqlite.scan_prefix("c")), vec![&b"c"[..], b"ca", b"cb"]);
+    assert_eq!(range_keys(unqlite.scan_prefix("c").rev()), vec![&b"cb"[..], b"ca", b"c"]);
+
+    assert!(!unqlite.is_transactional());
+    let err = unqlite.kv_delete_prefix("c").unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::NOTIMPLEMENTED));
+    let mut batch = WriteBatch::new();
+    batch.delete("a");
+    let err = batch.apply(&unqlite).unwrap_err();
+    assert_eq!(err.error.kind(), Some(ErrorKind::NOTIMPLEMENTED));
+    assert!(unqlite.kv_contains("a"));
+
+    let err = engine::register("other", BTreeEngine::default).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::LOCKED));
+}
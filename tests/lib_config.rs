@@ -0,0 +1,33 @@
+//! Library settings are process-wide and can only be applied before the library is
+//! initialized, so this lives in its own test binary.
+extern crate unqlite;
+
+use unqlite::lib_config::{self, LibConfig, StdMutex, ThreadLevel};
+use unqlite::{ErrorKind, UnQLite, KV};
+
+#[test]
+fn lib_config() {
+    let err = LibConfig::new().page_size(1000).init().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::INVALID));
+    assert_eq!(lib_config::info().page_size, 4096);
+
+    let mut config = LibConfig::new();
+    config.page_size(8192).mutex::<StdMutex>();
+    if cfg!(feature = "enable-threads") {
+        config.thread_level(ThreadLevel::Multi);
+    }
+    config.init().expect("init before the first open");
+
+    let info = lib_config::info();
+    assert_eq!(info.page_size, 8192);
+    assert!(info.user_mutex);
+    assert_eq!(info.threadsafe, cfg!(feature = "enable-threads"));
+    assert!(!info.version.is_empty());
+
+    let unqlite = UnQLite::create_in_memory();
+    unqlite.kv_store("key", "value").unwrap();
+    assert_eq!(unqlite.kv_fetch("key").unwrap(), b"value");
+
+    let err = LibConfig::new().init().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::LOCKED));
+}
@@ -0,0 +1,42 @@
+//! The allocator is process-wide and can only be installed before the library is initialized,
+//! so this lives in its own test binary.
+extern crate unqlite;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use unqlite::memory::{self, Memory};
+use unqlite::{ErrorKind, UnQLite, KV};
+
+#[test]
+fn memory_limit() {
+    let failures = Arc::new(AtomicUsize::new(0));
+    let counter = failures.clone();
+    Memory::new()
+        .limit(64 << 20)
+        .on_oom(move |oom| {
+            assert!(oom.requested > 0);
+            counter.fetch_add(1, Ordering::SeqCst);
+            false
+        })
+        .install()
+        .expect("install before the first open");
+
+    let unqlite = UnQLite::create_in_memory();
+    unqlite.kv_store("key", "value").unwrap();
+    let stats = memory::stats();
+    assert!(stats.current > 0);
+    assert!(stats.peak >= stats.current);
+    assert_eq!(stats.limit, Some(64 << 20));
+
+    memory::set_limit(Some(stats.current));
+    let err = unqlite.kv_store("big", vec![0u8; 1 << 20]).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::NOMEM));
+    assert!(failures.load(Ordering::SeqCst) > 0);
+
+    memory::set_limit(None);
+    unqlite.kv_store("big", vec![0u8; 1 << 20]).unwrap();
+    assert!(memory::stats().peak > stats.peak);
+
+    let err = Memory::new().install().unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::LOCKED));
+}
@@ -0,0 +1,31 @@
+//! The VFS is process-wide and can only be installed before the library is initialized, so
+//! this lives in its own test binary.
+extern crate unqlite;
+
+use std::path::Path;
+use unqlite::vfs::{self, Access, MemoryVfs, Vfs};
+use unqlite::{ErrorKind, OpenOptions, Transaction, UnQLite, KV};
+
+#[test]
+fn memory_vfs() {
+    let memory = MemoryVfs::new();
+    vfs::register(memory.clone()).expect("register before the first open");
+
+    let unqlite = OpenOptions::new().create(true).open("vfs-test.db").unwrap();
+    unqlite.kv_store("key", "value").unwrap();
+    unqlite.commit().unwrap();
+    assert!(memory.access("vfs-test.db", Access::Exists).unwrap());
+    assert!(!Path::new("vfs-test.db").exists());
+    drop(unqlite);
+
+    let unqlite = OpenOptions::new().write(true).open("vfs-test.db").unwrap();
+    assert_eq!(unqlite.kv_fetch("key").unwrap(), b"value");
+
+    let temp = UnQLite::create_temp();
+    temp.kv_store("key", "value").unwrap();
+    temp.commit().unwrap();
+    drop(temp);
+
+    let err = vfs::register(MemoryVfs::new()).unwrap_err();
+    assert_eq!(err.kind(), Some(ErrorKind::LOCKED));
+}